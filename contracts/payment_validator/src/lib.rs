@@ -1,9 +1,9 @@
 #![no_std]
-use soroban_sdk::{contract, contractimpl, contracttype, contracterror, Address, Env, String, symbol_short};
+use soroban_sdk::{contract, contractimpl, contracttype, contracterror, xdr::{FromXdr, ToXdr}, Address, Bytes, BytesN, Env, IntoVal, String, Symbol, TryFromVal, Val, Vec, symbol_short};
 
 /// Payment session data stored in contract
 /// Contract validates payment rules - actual payments go to merchant addresses
-#[derive(Clone)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 #[contracttype]
 pub struct PaymentSession {
     pub memo: String,           // Payment session ID (memo)
@@ -11,31 +11,638 @@ pub struct PaymentSession {
     pub amount: i128,           // Expected minimum amount
     pub is_active: bool,        // Whether session is still active
     pub created_at: u64,        // Timestamp when session was created
+    pub asset: Option<Address>, // Token contract the payment is expected in (None = any)
+    pub payer: Option<Address>, // Address that paid (set on completion when known)
+    pub fee_bps: Option<u32>,   // Per-session platform fee (None = global fee)
+    pub fee: i128,              // Platform fee charged on completion
+    pub status: SessionStatus,  // Lifecycle state (is_active mirrors Active)
+    pub escrow: bool,           // Validated payments are held until released
+    pub expires_at: Option<u64>, // Timestamp after which payments are rejected
+    pub amount_mode: AmountMode, // How a paid amount is compared against `amount`
+    pub max_overpayment: i128,  // Largest accepted surplus in Minimum mode (0 = unlimited)
+    pub paid_so_far: i128,      // Sum of partial payments received
+    pub partial_count: u32,     // Number of partial payments received
+    pub max_partials: u32,      // Partial payments allowed before rejecting more
+    pub require_customer_auth: bool, // Payer must co-sign completion
+    pub refund_of: Option<String>, // Original session this session refunds
+    pub completion_threshold_bps: u32, // Share of amount that completes via partials
+    pub completed_at: Option<u64>, // Timestamp the payment was validated (None while active)
+    pub actual_amount: Option<i128>, // Amount actually paid (set on completion)
+    pub min_confirmations: u32, // Ledger confirmations the backend must wait for (audit only)
+    pub confirmations_at_validation: u32, // Confirmations the backend attested when validating
+    pub external_ref: Option<String>, // Merchant's own order ID (unique across sessions)
+    pub allowed_payers: Vec<Address>, // Addresses that may pay (empty = anyone)
+    pub allow_partial: bool,          // Whether record_partial_payment is accepted
+    pub last_activity: u64,           // Ledger time of the last change to the session
+    pub min_partial_bps: u32,         // Smallest partial as a share of amount (0 = any)
+    pub callback: Option<Address>,    // Contract notified via `on_payment(memo)` on completion
+    pub callback_attempts: u32,       // Times the callback has been invoked
+    pub callback_delivered: bool,     // Whether an invocation succeeded
+    pub tolerance_bps: u32,           // Accepted shortfall as a share of amount
+    pub priority: u32,                // Merchant routing label, 0..=MAX_PRIORITY (not enforced)
+    pub fee_recipient: Option<Address>, // Affiliate owed the fee (None = platform)
+    pub fiat_code: Option<String>,    // Currency the amount was quoted in (e.g. "USD")
+    pub refunded_amount: i128,        // Sum of partial refunds recorded against the payment
+    pub hold_expires_at: Option<u64>, // Timestamp an escrow hold lapses into a refund (set on Held)
+    pub require_merchant_auth: bool,  // Merchant must co-sign completion
+}
+
+impl PaymentSession {
+    /// Move to a new lifecycle state, keeping is_active in sync
+    fn set_status(&mut self, status: SessionStatus) {
+        self.status = status;
+        self.is_active = status == SessionStatus::Active;
+    }
+}
+
+/// Session lifecycle state
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[contracttype]
+#[repr(u32)]
+pub enum SessionStatus {
+    Active = 0,
+    Completed = 1,
+    Cancelled = 2,
+    Held = 3,
+    Refunded = 4,
+    Authorized = 5,
+    Expired = 6, // Active session past its expiry (reported by get_session_live)
+    Disputed = 7, // Completed payment frozen until resolve_dispute
 }
 
-#[contracterror]
-#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+/// How a paid amount is matched against the session amount
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[contracttype]
 #[repr(u32)]
-pub enum Error {
+pub enum AmountMode {
+    #[default]
+    Minimum = 0, // At least `amount` (optionally bounded by max_overpayment)
+    Exact = 1,   // Exactly `amount`
+}
+
+/// Payload of the payment completion event
+#[derive(Clone)]
+#[contracttype]
+pub struct CompletionEvent {
+    pub memo: String,
+    pub merchant: Address,
+    pub amount: i128,        // Expected amount the session was registered with
+    pub actual_amount: i128, // Amount actually paid
+    pub payer: Option<Address>,
+    pub fee: i128,
+    pub ledger_seq: u32,     // Ledger sequence the payment was validated in
+    pub confirmations: u32,  // Confirmations the backend attested (0 if not supplied)
+    pub receipt: BytesN<32>, // Attestation hash, as attest_completion computes it
+    pub priority: u32,       // Session's priority label
+    pub fee_recipient: Option<Address>, // Who the fee is owed to (None = platform)
+    pub fiat_code: Option<String>, // Session's quote currency
+}
+
+/// Entry in the recent-completions buffer
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct CompletionRecord {
+    pub memo: String,
+    pub merchant: Address,
+    pub amount: i128,   // Amount paid
+    pub timestamp: u64, // Ledger time of completion
+}
+
+/// Addresses that control the contract, for key audits
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct Controllers {
+    pub backend: Address,               // Registers and validates sessions
+    pub admin: Option<Address>,         // Manages contract configuration
+    pub pending_admin: Option<Address>, // Proposed admin awaiting accept_admin
+}
+
+/// Merchant-signed order decoded from register_signed_session's payload (XDR encoded)
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct SignedOrder {
+    pub memo: String,
+    pub amount: i128,
+}
+
+/// Fee schedule tier: amounts of at least min_amount pay bps
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct FeeTier {
+    pub min_amount: i128,
+    pub bps: u32,
+}
+
+/// Stored session with its status as of the current ledger time
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct SessionView {
+    pub session: PaymentSession,
+    pub status: SessionStatus, // Expired when an active session is past expires_at
+}
+
+/// Funds reserved by authorize, awaiting capture or void
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct Authorization {
+    pub amount: i128,    // Amount that will be captured
+    pub expires_at: u64, // Capture is refused (and the session voided) from this timestamp
+}
+
+/// Completed payment totals over a time window
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[contracttype]
+pub struct WindowStats {
+    pub count: u32,   // Completed payments
+    pub amount: i128, // Sum of completed payment amounts
+}
+
+/// Contract-wide counters, live from get_stats or frozen by snapshot_stats
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[contracttype]
+pub struct ContractStats {
+    pub sessions: u64,  // Sessions ever registered
+    pub active: u64,    // Sessions currently active
+    pub completed: u64, // Payments completed
+    pub volume: i128,   // Sum of completed payment amounts
+    pub fees: i128,     // Sum of platform fees charged on completion
+}
+
+/// Every tunable limit in one read (0 means the limit is off unless noted)
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[contracttype]
+pub struct ContractLimits {
+    pub fee_bps: u32,                       // Global platform fee
+    pub max_ttl_seconds: u64,               // Longest session lifetime
+    pub creation_fee: i128,                 // Accrued per registered session
+    pub tick_size: i128,                    // Amount granularity (1 = any)
+    pub max_sessions_per_merchant: u32,     // Active sessions per merchant
+    pub registration_cooldown_seconds: u64, // Gap between a merchant's registrations
+    pub min_age_seconds: u64,               // Wait before a session can be paid
+    pub extend_on_partial_seconds: u64,     // Expiry extension per partial
+    pub partial_cooldown_seconds: u64,      // Gap between partials on a session
+    pub refund_window_seconds: u64,         // mark_refunded window after completion
+    pub deactivation_lock_seconds: u64,     // Wait before the backend can cancel
+    pub approval_threshold: i128,           // Amount from which the allowlist applies
+    pub min_donation: i128,                 // Smallest donation
+    pub deadman_seconds: u64,               // Backend silence before the contract pauses
+}
+
+/// Breakdown of sessions by how they left (or haven't left) the Active state
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[contracttype]
+pub struct StatusCounts {
+    pub active: u64,    // Sessions currently active
+    pub completed: u64, // Payments completed
+    pub cancelled: u64, // Sessions deactivated, closed or voided before expiry
+    pub expired: u64,   // Sessions deactivated after their expiry
+}
+
+/// Lifecycle timestamps of a session
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct SessionTimestamps {
+    pub created_at: u64,
+    pub last_activity: u64,
+    pub completed_at: Option<u64>, // None until the payment is validated
+    pub expires_at: Option<u64>,   // None for sessions that never expire
+}
+
+/// Compact view of a session for bulk exports
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[contracttype]
+pub struct SessionSummary {
+    pub memo: String,
+    pub status: SessionStatus,
+    pub amount: i128,
+    pub created_at: u64,
+}
+
+/// Deployment configuration supplied at initialize
+#[derive(Clone, Default)]
+#[contracttype]
+pub struct InitConfig {
+    pub completion_topic: Option<Symbol>, // Topic for completion events (default `valid`)
+    pub registration_topic: Option<Symbol>, // Topic for registration events (default `reg_sess`)
+    pub max_ttl_seconds: u64,             // Longest session lifetime (0 = unlimited)
+    pub creation_fee: i128,               // Fee accrued to the merchant per registered session
+    pub max_sessions_per_merchant: u32,   // Active sessions allowed per merchant (0 = unlimited)
+    pub tick_size: i128,                  // Registered amounts must be multiples of this (0/1 = any)
+    pub registration_cooldown_seconds: u64, // Minimum gap between a merchant's registrations (0 = none)
+    pub min_age_seconds: u64,             // Sessions can't be paid until this old (0 = immediately)
+    pub extend_on_partial_seconds: u64,   // Expiry pushed out by this on each partial (0 = fixed)
+    pub min_donation: i128,               // Smallest amount validate_donation accepts
+    pub approved_merchants: Option<Vec<Address>>, // Seeds the merchant allowlist (None = any merchant)
+    pub refund_window_seconds: u64,       // mark_refunded allowed this long after completion (0 = always)
+    pub deactivation_lock_seconds: u64,   // Backend can't cancel sessions younger than this (0 = no lock)
+    pub approval_threshold: i128,         // Allowlist only applies to amounts at or above this (0 = all)
+    pub partial_cooldown_seconds: u64,    // Minimum gap between partials on one session (0 = none)
+    pub deadman_seconds: u64,             // Pause after this long without a backend call (0 = never)
+}
+
+/// Optional per-session parameters accepted by `register_session_with`
+#[derive(Clone, Default)]
+#[contracttype]
+pub struct SessionOptions {
+    pub asset: Option<Address>, // Token contract the payment must be made in
+    pub fee_bps: Option<u32>,   // Platform fee overriding the global fee
+    pub escrow: bool,           // Hold validated payments until release_escrow
+    pub ttl_seconds: u64,       // Session lifetime (0 = until max TTL, or never)
+    pub amount_mode: AmountMode, // Minimum (default) or Exact matching
+    pub max_overpayment: i128,  // Largest accepted surplus in Minimum mode (0 = unlimited)
+    pub max_partials: u32,      // Partial payments allowed (0 = DEFAULT_MAX_PARTIALS)
+    pub require_customer_auth: bool, // Only validate_payment_full with the payer's signature completes
+    pub refund_of: Option<String>, // Memo of the original session being refunded
+    pub completion_threshold_bps: u32, // Partials complete at this share of amount (0 = 10000)
+    pub min_confirmations: u32, // Confirmations the backend requires before validating
+    pub external_ref: Option<String>, // Merchant's order ID, for get_session_by_ref
+    pub allowed_payers: Option<Vec<Address>>, // Up to MAX_ALLOWED_PAYERS payers (None = anyone)
+    pub allow_partial: Option<bool>, // Accept partial payments (None = allowed)
+    pub min_partial_bps: u32,        // Reject partials below this share of amount (0 = any)
+    pub callback: Option<Address>,   // Contract to notify when the session completes
+    pub tolerance_bps: u32,          // Accept payments short by up to this share of amount
+    pub priority: u32,               // Routing label for the merchant's systems (0..=MAX_PRIORITY)
+    pub fee_recipient: Option<Address>, // Affiliate the fee is owed to (None = platform)
+    pub idempotency_key: Option<String>, // Retries with the same key return the first session
+    pub fiat_code: Option<String>,   // Quote currency, at most MAX_FIAT_CODE_LEN chars
+    pub require_merchant_auth: bool, // Every completion path needs the merchant's signature
+}
+
+/// Storage keys, typed so sessions, indexes and configuration can't collide
+#[derive(Clone)]
+#[contracttype]
+pub enum DataKey {
+    // Instance storage: configuration and counters
+    Backend,
+    Admin,
+    PendingAdmin,
+    CompletionTopic,
+    RegistrationTopic,
+    FeeBps,
+    FeeTiers,
+    Assets,
+    SessionCount,
+    ActiveCount,
+    EventSeq,
+    RegistrationPaused,
+    MerchantAllowlist,
+    Outstanding,
+    DefaultMerchant,
+    SchemaVersion,
+    InitializedAt,
+    Sealed,
+    LastBackendCall,
+    Setting(Setting),
+    Counter(Counter),
+    Volume,
+    FeeTotal,
+    // Persistent storage: sessions and indexes
+    Session(String),
+    SessionIndex(u64),
+    Index(IndexEntry),
+    Refunds(String),
+    MerchantActive(Address),
+    Bucket(u64),
+    MerchantTotal(Address),
+    Accrued(Address),
+    MerchantKey(Address),
+    LastRegistration(Address),
+    FeeExempt(Address),
+    Authorization(String),
+    MemoUsed(String),
+    ExternalRef(String),
+    Snapshot(u64),
+    ApprovedMerchant(Address),
+    RecentCompletions,
+    MemoHash(BytesN<32>),
+    PaidBy(Address, Address),
+    AssetMax(Address),
+    MerchantAssets(Address),
+    PlatformFees(Address),
+    Idempotency(String),
+    MemoPrefix(Address),
+}
+
+/// Declares Error together with ERROR_CATALOG so the catalog can't drift from the enum
+macro_rules! contract_errors {
+    ($($name:ident = $code:tt,)*) => {
+        #[contracterror]
+        #[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+        #[repr(u32)]
+        pub enum Error {
+            $($name = $code,)*
+        }
+        
+        /// Every error code with its variant name, in declaration order
+        const ERROR_CATALOG: &[(u32, &str)] = &[$(($code, stringify!($name)),)*];
+    };
+}
+
+contract_errors! {
     InvalidMemo = 1,
     SessionNotFound = 2,
     InsufficientAmount = 3,
     SessionExpired = 4,
     Unauthorized = 5,
+    AssetNotAllowed = 6,
+    AlreadyInactive = 7,
+    InvalidBps = 8,
+    Overflow = 9,
+    SessionAlreadyExists = 10,
+    InvalidStatus = 11,
+    TtlTooLong = 12,
+    SessionNotStarted = 13,
+    AmountMismatch = 14,
+    NotCompleted = 15,
+    TooManyPartials = 16,
+    TooManyAssets = 17,
+    MerchantLimitReached = 18,
+    InvalidFeeSchedule = 19,
+    InvalidAmount = 20,
+    BatchTooLarge = 21,
+    BadSignature = 22,
+    RateLimited = 23,
+    DuplicateReference = 24,
+    ContractPaused = 25,
+    WrongPayer = 26,
+    TooManyPayers = 27,
+    SnapshotExists = 28,
+    AlreadyCompleted = 29,
+    MerchantNotApproved = 30,
+    SessionTooNew = 31,
+    PartialNotAllowed = 32,
+    NoDefaultMerchant = 33,
+    SchemaMismatch = 34,
+    RefundWindowClosed = 35,
+    InvalidPriority = 36,
+    CasMismatch = 37,
+    DeactivationLocked = 38,
+    HasPartialPayment = 39,
+    DeadlinePassed = 40,
+    ContractSealed = 41,
+    InvalidFiatCode = 42,
+    RefundExceedsPaid = 43,
+}
+
+/// Tunable limits set at initialize or by the admin, stored under `DataKey::Setting`
+#[derive(Clone)]
+#[contracttype]
+pub enum Setting {
+    MaxTtl,
+    CreationFee,
+    TickSize,
+    Cooldown,
+    MaxSessions,
+    MinAge,
+    MinDonation,
+    ExtendOnPartial,
+    RefundWindow,
+    DeactivationLock,
+    ApprovalThreshold,
+    PartialCooldown,
+    Deadman,
+}
+
+/// Lifecycle counters, stored under `DataKey::Counter`
+#[derive(Clone)]
+#[contracttype]
+pub enum Counter {
+    Completed,
+    Cancelled,
+    Expired,
+    Donations,
+    Nonce,
+}
+
+/// Paged index, named by what its items share
+#[derive(Clone)]
+#[contracttype]
+pub enum Index {
+    Merchant(Address), // Memos of a merchant's active sessions
+    Asset(Address),    // Memos of active sessions expecting payment in an asset
+    ActiveMerchants,   // Merchants with at least one active session
+}
+
+/// Entries of a paged index, stored under `DataKey::Index`
+/// Pages hold up to INDEX_PAGE_SIZE items so no single entry grows with the index
+#[derive(Clone)]
+#[contracttype]
+pub enum IndexEntry {
+    Len(Index),                  // Items in the index
+    Page(Index, u32),            // Items at positions [n * INDEX_PAGE_SIZE, (n + 1) * INDEX_PAGE_SIZE)
+    Slot(Index, String),         // Position of a memo, for swap-removal
+    AddressSlot(Index, Address), // Position of an address, for swap-removal
+}
+
+/// Items a paged index can hold, each with its own slot key
+trait IndexItem: IntoVal<Env, Val> + TryFromVal<Env, Val> + Clone {
+    fn slot(&self, index: &Index) -> IndexEntry;
+}
+
+impl IndexItem for String {
+    fn slot(&self, index: &Index) -> IndexEntry {
+        IndexEntry::Slot(index.clone(), self.clone())
+    }
+}
+
+impl IndexItem for Address {
+    fn slot(&self, index: &Index) -> IndexEntry {
+        IndexEntry::AddressSlot(index.clone(), self.clone())
+    }
 }
 
+/// Storage layout this code expects; bump it when stored data needs migrating
+const SCHEMA_VERSION: u32 = 1;
+
+/// Basis-point denominator (10000 bps = 100%)
+const MAX_BPS: u32 = 10_000;
+
+/// Longest canonical memo (Stellar text memos are capped at 28 bytes)
+const MAX_MEMO_LEN: usize = 28;
+
+/// Longest raw memo accepted before trimming
+const MAX_RAW_MEMO_LEN: usize = 64;
+
+/// Partial payments allowed per session unless overridden
+const DEFAULT_MAX_PARTIALS: u32 = 10;
+
+/// Width of a stats bucket in seconds
+const STATS_BUCKET_SECONDS: u64 = 3600;
+
+/// Most buckets get_stats_since sums (one week of hourly buckets)
+const MAX_STATS_BUCKETS: u64 = 168;
+
+/// Most sessions cancel_merchant_sessions deactivates per call
+const MAX_CANCEL_BATCH: u32 = 25;
+
+/// Largest accepted-asset allowlist
+const MAX_ACCEPTED_ASSETS: u32 = 50;
+
+/// Most memos returned by a paginated read
+const MAX_PAGE_SIZE: u32 = 50;
+
+/// Items stored per page of a paged index
+const INDEX_PAGE_SIZE: u32 = 64;
+
+/// Most tiers in a fee schedule
+const MAX_FEE_TIERS: u32 = 10;
+
+/// Most entries accepted by a batch call
+const MAX_BATCH_SIZE: u32 = 50;
+
+/// Most merchants returned by get_active_merchants (one index page)
+const MAX_ACTIVE_MERCHANTS: u32 = INDEX_PAGE_SIZE;
+
+/// Hash bytes encoded into the internal memo of a hash-memo session (22 hex chars)
+const HASH_MEMO_BYTES: usize = 11;
+
+/// Prefixes of the memos the contract generates (donations and hash-memo sessions)
+const RESERVED_MEMO_PREFIXES: [&[u8]; 2] = [b"don_", b"h_"];
+
+/// Completions kept by recent_completions
+const MAX_RECENT_COMPLETIONS: u32 = 20;
+
+/// Most merchants initialize can approve
+const MAX_INIT_MERCHANTS: u32 = 50;
+
+/// Longest fiat currency code a session can carry
+const MAX_FIAT_CODE_LEN: u32 = 8;
+
+/// Highest priority label a session can carry
+const MAX_PRIORITY: u32 = 3;
+
+/// Most payers a session's allowed-payer list can hold
+const MAX_ALLOWED_PAYERS: u32 = 10;
+
+/// How long an authorization can be captured before it lapses (7 days)
+const AUTH_WINDOW_SECONDS: u64 = 7 * 24 * 3600;
+
+/// How long a held escrow payment can be released before it is refunded (7 days)
+const HOLD_WINDOW_SECONDS: u64 = 7 * 24 * 3600;
+
+/// Bits packed by session_flags
+const FLAG_EXISTS: u32 = 1 << 0;
+const FLAG_ACTIVE: u32 = 1 << 1;
+const FLAG_EXPIRED: u32 = 1 << 2;
+const FLAG_PARTIAL: u32 = 1 << 3;
+const FLAG_COMPLETED: u32 = 1 << 4;
+const FLAG_REFUNDED: u32 = 1 << 5;
+
+/// Bits packed by role_of
+const ROLE_BACKEND: u32 = 1 << 0;
+const ROLE_ADMIN: u32 = 1 << 1;
+const ROLE_MERCHANT: u32 = 1 << 2;
+
 #[contract]
 pub struct ChainPeValidator;
 
 #[contractimpl]
 impl ChainPeValidator {
     /// Initialize contract with backend address (only backend can register sessions)
-    pub fn initialize(env: Env, backend_address: Address) {
-        let backend_key = symbol_short!("BACKEND");
+    /// and an optional admin that manages contract configuration
+    pub fn initialize(env: Env, backend_address: Address, admin: Option<Address>, config: InitConfig) {
+        let backend_key = DataKey::Backend;
         if env.storage().instance().has(&backend_key) {
             panic!("Already initialized");
         }
+        // Only the admin can reset a tripped dead-man switch
+        if config.deadman_seconds > 0 && admin.is_none() {
+            panic!("Dead-man switch requires an admin");
+        }
         env.storage().instance().set(&backend_key, &backend_address);
+        env.storage().instance().set(&DataKey::SchemaVersion, &SCHEMA_VERSION);
+        env.storage().instance().set(&DataKey::InitializedAt, &env.ledger().timestamp());
+        env.storage().instance().set(&DataKey::LastBackendCall, &env.ledger().timestamp());
+        if let Some(admin) = admin {
+            env.storage().instance().set(&DataKey::Admin, &admin);
+        }
+        if let Some(topic) = config.completion_topic {
+            env.storage().instance().set(&DataKey::CompletionTopic, &topic);
+        }
+        if let Some(topic) = config.registration_topic {
+            env.storage().instance().set(&DataKey::RegistrationTopic, &topic);
+        }
+        if config.max_ttl_seconds > 0 {
+            set_setting(&env, Setting::MaxTtl, &config.max_ttl_seconds);
+        }
+        if config.creation_fee > 0 {
+            set_setting(&env, Setting::CreationFee, &config.creation_fee);
+        }
+        if config.tick_size > 1 {
+            set_setting(&env, Setting::TickSize, &config.tick_size);
+        }
+        if config.registration_cooldown_seconds > 0 {
+            set_setting(&env, Setting::Cooldown, &config.registration_cooldown_seconds);
+        }
+        if config.extend_on_partial_seconds > 0 {
+            set_setting(&env, Setting::ExtendOnPartial, &config.extend_on_partial_seconds);
+        }
+        if config.refund_window_seconds > 0 {
+            set_setting(&env, Setting::RefundWindow, &config.refund_window_seconds);
+        }
+        if config.deactivation_lock_seconds > 0 {
+            set_setting(&env, Setting::DeactivationLock, &config.deactivation_lock_seconds);
+        }
+        if config.approval_threshold > 0 {
+            set_setting(&env, Setting::ApprovalThreshold, &config.approval_threshold);
+        }
+        if config.partial_cooldown_seconds > 0 {
+            set_setting(&env, Setting::PartialCooldown, &config.partial_cooldown_seconds);
+        }
+        if config.deadman_seconds > 0 {
+            set_setting(&env, Setting::Deadman, &config.deadman_seconds);
+        }
+        if config.min_donation > 0 {
+            set_setting(&env, Setting::MinDonation, &config.min_donation);
+        }
+        if config.min_age_seconds > 0 {
+            set_setting(&env, Setting::MinAge, &config.min_age_seconds);
+        }
+        if let Some(merchants) = config.approved_merchants {
+            if merchants.len() > MAX_INIT_MERCHANTS {
+                panic!("Too many approved merchants");
+            }
+            for merchant in merchants.iter() {
+                approve(&env, &merchant);
+            }
+        }
+        if config.max_sessions_per_merchant > 0 {
+            set_setting(&env, Setting::MaxSessions, &config.max_sessions_per_merchant);
+        }
+    }
+    
+    /// The contract's current time: the ledger close timestamp in Unix seconds (UTC)
+    /// Every expiry, TTL, cooldown and stats-bucket comparison uses this value
+    pub fn now(env: Env) -> u64 {
+        env.ledger().timestamp()
+    }
+    
+    /// Seconds since initialize (0 for deployments that predate the recorded start time)
+    pub fn get_age(env: Env) -> u64 {
+        let initialized_at: u64 = env
+            .storage()
+            .instance()
+            .get(&DataKey::InitializedAt)
+            .unwrap_or(env.ledger().timestamp());
+        env.ledger().timestamp().saturating_sub(initialized_at)
+    }
+    
+    /// Whether initialize has been called (the backend address is set)
+    pub fn is_initialized(env: Env) -> bool {
+        env.storage().instance().has(&DataKey::Backend)
+    }
+    
+    /// Backend registers a payment session and returns its payment reference
+    /// `chainpe:<memo>:<amount>` for embedding in a Stellar payment URI
+    pub fn register_session_with_link(
+        env: Env,
+        memo: String,
+        merchant: Address,
+        amount: i128,
+    ) -> Result<String, Error> {
+        require_backend(&env)?;
+        let session = register(&env, memo, merchant, amount, SessionOptions::default())?;
+        Ok(payment_link(&env, &session.memo, session.amount))
     }
     
     /// Backend registers a payment session (called when user creates checkout)
@@ -44,34 +651,118 @@ impl ChainPeValidator {
         memo: String,
         merchant: Address,
         amount: i128,
+    ) -> Result<(), Error> {
+        Self::register_session_with(env, memo, merchant, amount, SessionOptions::default())
+    }
+    
+    /// Backend registers a payment session with optional parameters
+    pub fn register_session_with(
+        env: Env,
+        memo: String,
+        merchant: Address,
+        amount: i128,
+        options: SessionOptions,
     ) -> Result<(), Error> {
         // Only backend can register sessions
-        let backend_key = symbol_short!("BACKEND");
-        let backend: Address = env
+        require_backend(&env)?;
+        
+        register(&env, memo, merchant, amount, options)?;
+        Ok(())
+    }
+    
+    /// Backend registers a session for payments carrying a 32-byte MEMO_HASH
+    /// The session is stored under an internal memo derived from the hash
+    pub fn register_session_hash(
+        env: Env,
+        memo_hash: BytesN<32>,
+        merchant: Address,
+        amount: i128,
+    ) -> Result<(), Error> {
+        require_backend(&env)?;
+        
+        let key = DataKey::MemoHash(memo_hash.clone());
+        if env.storage().persistent().has(&key) {
+            return Err(Error::SessionAlreadyExists);
+        }
+        let memo = hash_memo(&env, &memo_hash);
+        let session = create_session(&env, memo, merchant, amount, SessionOptions::default(), true)?;
+        env.storage().persistent().set(&key, &session.memo);
+        Ok(())
+    }
+    
+    /// Validate a payment that carried a MEMO_HASH
+    pub fn validate_payment_hash(env: Env, memo_hash: BytesN<32>, amount: i128) -> Result<bool, Error> {
+        require_backend(&env)?;
+        
+        let memo: String = env
             .storage()
-            .instance()
-            .get(&backend_key)
-            .ok_or(Error::Unauthorized)?;
-        backend.require_auth();
+            .persistent()
+            .get(&DataKey::MemoHash(memo_hash))
+            .ok_or(Error::SessionNotFound)?;
+        complete_payment(&env, memo, amount, None, 0)
+    }
+    
+    /// Backend records a memo-less donation to the default merchant as a completed session
+    /// The session gets an internal `don_<n>` memo and the donated amount as its expected amount
+    /// Donations aren't merchant registrations, so the registration pause, memo prefix,
+    /// session cap and cooldown don't apply
+    pub fn validate_donation(env: Env, amount: i128, payer: Address) -> Result<String, Error> {
+        require_backend(&env)?;
         
-        // Get current ledger timestamp
-        let created_at = env.ledger().timestamp();
+        let merchant: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::DefaultMerchant)
+            .ok_or(Error::NoDefaultMerchant)?;
+        let min_donation: i128 = setting(&env, Setting::MinDonation).unwrap_or(0);
+        if amount < min_donation || amount <= 0 {
+            return Err(Error::InsufficientAmount);
+        }
         
-        // Store session data
-        let session = PaymentSession {
-            memo: memo.clone(),
-            merchant,
-            amount,
-            is_active: true,
-            created_at,
-        };
+        let count = bump_counter(&env, Counter::Donations);
+        let memo = donation_memo(&env, count);
+        let options = SessionOptions::default();
+        let session = create_session(&env, memo.clone(), merchant, amount, options, false)?;
+        finish_payment(&env, memo.clone(), session, amount, Some(payer), 0)?;
+        Ok(memo)
+    }
+    
+    /// Backend registers a session, or returns the existing one on retries
+    /// Fails with SessionAlreadyExists if the existing session's merchant or amount differ
+    pub fn register_or_get(
+        env: Env,
+        memo: String,
+        merchant: Address,
+        amount: i128,
+    ) -> Result<PaymentSession, Error> {
+        require_backend(&env)?;
         
-        env.storage().persistent().set(&memo, &session);
+        let canonical = normalize_memo(&env, &memo)?;
+        let key = DataKey::Session(canonical);
+        if let Some(existing) = env.storage().persistent().get::<_, PaymentSession>(&key) {
+            if existing.merchant != merchant || existing.amount != amount {
+                return Err(Error::SessionAlreadyExists);
+            }
+            return Ok(existing);
+        }
         
-        // Emit event
-        env.events().publish((symbol_short!("reg_sess"),), memo);
+        register(&env, memo, merchant, amount, SessionOptions::default())
+    }
+    
+    /// Backend registers and validates a session in one call for instantly confirmed rails
+    /// Nothing is stored if validation fails
+    pub fn register_and_validate(
+        env: Env,
+        memo: String,
+        merchant: Address,
+        amount: i128,
+        paid_amount: i128,
+    ) -> Result<PaymentSession, Error> {
+        require_backend(&env)?;
         
-        Ok(())
+        let session = register(&env, memo, merchant, amount, SessionOptions::default())?;
+        complete_payment(&env, session.memo.clone(), paid_amount, None, 0)?;
+        load_session(&env, &session.memo)
     }
     
     /// Validate payment - called by backend after detecting payment
@@ -81,97 +772,5858 @@ impl ChainPeValidator {
         memo: String,
         amount: i128,
     ) -> Result<bool, Error> {
-        // Get session
-        let session: PaymentSession = env
-            .storage()
-            .persistent()
-            .get(&memo)
-            .ok_or(Error::SessionNotFound)?;
+        complete_payment(&env, memo, amount, None, 0)
+    }
+    
+    /// Validate payment only while the ledger sequence is at most `valid_until_ledger`
+    /// so a transaction that sat in a queue can't complete the session late
+    pub fn validate_before_ledger(
+        env: Env,
+        memo: String,
+        amount: i128,
+        valid_until_ledger: u32,
+    ) -> Result<bool, Error> {
+        require_backend(&env)?;
         
-        // Check if session is active
-        if !session.is_active {
-            env.events().publish((symbol_short!("expired"),), memo);
-            return Err(Error::SessionExpired);
+        if env.ledger().sequence() > valid_until_ledger {
+            return Err(Error::DeadlinePassed);
         }
+        complete_payment(&env, memo, amount, None, 0)
+    }
+    
+    /// Validate payment and return the completed session with its receipt hash
+    pub fn validate_with_receipt(
+        env: Env,
+        memo: String,
+        amount: i128,
+    ) -> Result<(PaymentSession, BytesN<32>), Error> {
+        require_backend(&env)?;
         
-        // Check if amount is sufficient
-        if amount < session.amount {
-            env.events().publish(
-                (symbol_short!("insuff"),),
-                (memo.clone(), amount, session.amount)
-            );
-            return Err(Error::InsufficientAmount);
-        }
+        let memo = normalize_memo(&env, &memo)?;
+        complete_payment(&env, memo.clone(), amount, None, 0)?;
+        let session = load_session(&env, &memo)?;
+        let receipt = attestation_hash(&env, &session);
+        Ok((session, receipt))
+    }
+    
+    /// Validate payment and record the payer in the same write that completes the session
+    pub fn validate_payment_full(
+        env: Env,
+        memo: String,
+        amount: i128,
+        payer: Address,
+    ) -> Result<bool, Error> {
+        require_backend(&env)?;
+        complete_payment(&env, memo, amount, Some(payer), 0)
+    }
+    
+    /// Backend validates a payment the session's merchant co-signs, for dispute-sensitive flows
+    /// Sessions registered with `require_merchant_auth` can't complete any other way
+    pub fn validate_merchant_signed(env: Env, memo: String, amount: i128) -> Result<bool, Error> {
+        require_backend(&env)?;
         
-        // Mark session as completed (deactivate)
-        let mut updated_session = session.clone();
-        updated_session.is_active = false;
-        env.storage().persistent().set(&memo, &updated_session);
+        let memo = normalize_memo(&env, &memo)?;
+        let session = load_session(&env, &memo)?;
+        // Completion itself checks the signature for sessions that require it
+        if !session.require_merchant_auth {
+            session.merchant.require_auth();
+        }
+        complete_payment(&env, memo, amount, None, 0)
+    }
+    
+    /// Backend validates a payment, attesting how many ledger confirmations it waited for
+    /// The count is recorded for audit; the contract doesn't verify it
+    pub fn validate_with_confirmations(
+        env: Env,
+        memo: String,
+        amount: i128,
+        confirmations: u32,
+    ) -> Result<bool, Error> {
+        require_backend(&env)?;
+        complete_payment(&env, memo, amount, None, confirmations)
+    }
+    
+    /// Merchant registers the ed25519 public key it signs orders with
+    pub fn set_merchant_key(env: Env, merchant: Address, public_key: BytesN<32>) {
+        merchant.require_auth();
         
-        // Emit success event
-        env.events().publish(
-            (symbol_short!("valid"),),
-            (memo, session.merchant, amount)
-        );
+        env.storage()
+            .persistent()
+            .set(&DataKey::MerchantKey(merchant.clone()), &public_key);
+        publish_event(&env, symbol_short!("mer_key"), (merchant, public_key));
+    }
+    
+    /// Backend sets the prefix every memo registered for the merchant must start with
+    /// None lifts the restriction
+    pub fn set_memo_prefix(env: Env, merchant: Address, prefix: Option<String>) -> Result<(), Error> {
+        require_backend(&env)?;
         
-        Ok(true)
+        let key = DataKey::MemoPrefix(merchant.clone());
+        match &prefix {
+            Some(prefix) => {
+                let prefix = normalize_memo(&env, prefix)?;
+                env.storage().persistent().set(&key, &prefix);
+            }
+            None => env.storage().persistent().remove(&key),
+        }
+        publish_event(&env, symbol_short!("mer_pfx"), (merchant, prefix));
+        Ok(())
     }
     
-    /// Backend deactivates session (when expired or cancelled)
-    pub fn deactivate_session(env: Env, memo: String) -> Result<(), Error> {
-        let backend_key = symbol_short!("BACKEND");
-        let backend: Address = env
-            .storage()
-            .instance()
-            .get(&backend_key)
-            .ok_or(Error::Unauthorized)?;
-        backend.require_auth();
+    /// Backend registers a session from an order the merchant signed off-chain
+    /// order_bytes is the XDR of a SignedOrder; a signature that doesn't verify against the
+    /// merchant's key traps the invocation, and a missing key or undecodable order is BadSignature
+    pub fn register_signed_session(
+        env: Env,
+        order_bytes: Bytes,
+        merchant: Address,
+        signature: BytesN<64>,
+    ) -> Result<(), Error> {
+        require_backend(&env)?;
         
-        let mut session: PaymentSession = env
+        let public_key: BytesN<32> = env
             .storage()
             .persistent()
-            .get(&memo)
-            .ok_or(Error::SessionNotFound)?;
-        
-        session.is_active = false;
-        env.storage().persistent().set(&memo, &session);
+            .get(&DataKey::MerchantKey(merchant.clone()))
+            .ok_or(Error::BadSignature)?;
+        env.crypto().ed25519_verify(&public_key, &order_bytes, &signature);
         
-        env.events().publish((symbol_short!("deact"),), memo);
+        let order = SignedOrder::from_xdr(&env, &order_bytes).map_err(|_| Error::BadSignature)?;
+        register(&env, order.memo, merchant, order.amount, SessionOptions::default())?;
         Ok(())
     }
     
-    /// Get session details (for frontend verification)
-    pub fn get_session(env: Env, memo: String) -> Option<PaymentSession> {
-        env.storage().persistent().get(&memo)
-    }
-}
-
-#[cfg(test)]
-mod test {
-    use super::*;
-    use soroban_sdk::testutils::{Address as _, Ledger};
-    use soroban_sdk::{Address, Env};
-
-    #[test]
+    /// Backend records a partial payment; the session completes once the total reaches its amount
+    /// Returns the amount still due (0 once the session completes)
+    pub fn record_partial_payment(env: Env, memo: String, amount: i128) -> Result<i128, Error> {
+        require_backend(&env)?;
+        
+        let memo = normalize_memo(&env, &memo)?;
+        let mut session = load_payable_session(&env, &memo)?;
+        if !session.allow_partial {
+            return Err(Error::PartialNotAllowed);
+        }
+        // Partials carry no payer, so they could never complete a restricted session
+        check_payer_allowed(&session, &None)?;
+        if amount <= 0 {
+            return Err(Error::InsufficientAmount);
+        }
+        let min_partial = session
+            .amount
+            .checked_mul(session.min_partial_bps as i128)
+            .ok_or(Error::Overflow)?
+            / MAX_BPS as i128;
+        if amount < min_partial {
+            return Err(Error::InvalidAmount);
+        }
+        if session.partial_count >= session.max_partials {
+            return Err(Error::TooManyPartials);
+        }
+        let cooldown: u64 = setting(&env, Setting::PartialCooldown).unwrap_or(0);
+        if session.partial_count > 0
+            && env.ledger().timestamp() < session.last_activity.saturating_add(cooldown)
+        {
+            return Err(Error::RateLimited);
+        }
+        
+        session.paid_so_far = session.paid_so_far.checked_add(amount).ok_or(Error::Overflow)?;
+        session.partial_count += 1;
+        
+        let target = partial_target(&session)?;
+        if session.paid_so_far >= target {
+            if session.paid_so_far > session.amount {
+                check_overpayment(&session, session.paid_so_far)?;
+            }
+            let total = session.paid_so_far;
+            finish_payment(&env, memo, session, total, None, 0)?;
+            return Ok(0);
+        }
+        
+        // An engaged customer gets more time, up to the max TTL from creation
+        let extend: u64 = setting(&env, Setting::ExtendOnPartial).unwrap_or(0);
+        if let (Some(expires_at), true) = (session.expires_at, extend > 0) {
+            let mut extended = expires_at.saturating_add(extend);
+            let max_ttl: u64 = setting(&env, Setting::MaxTtl).unwrap_or(0);
+            if max_ttl > 0 {
+                extended = extended.min(session.created_at.saturating_add(max_ttl));
+            }
+            session.expires_at = Some(extended.max(expires_at));
+        }
+        
+        save_session(&env, &memo, &mut session);
+        publish_event(&env, symbol_short!("partial"), (memo, amount, session.paid_so_far));
+        Ok(target - session.paid_so_far)
+    }
+    
+    /// Backend deactivates session (when expired or cancelled)
+    pub fn deactivate_session(env: Env, memo: String) -> Result<(), Error> {
+        require_backend(&env)?;
+        
+        let memo = normalize_memo(&env, &memo)?;
+        let session = load_session(&env, &memo)?;
+        if !session.is_active {
+            return Err(Error::AlreadyInactive);
+        }
+        check_deactivation_lock(&env, &session)?;
+        
+        deactivate(&env, memo, session)?;
+        Ok(())
+    }
+    
+    /// Backend cancels a session only if nothing has been paid toward it
+    /// A partially paid session needs a deliberate refund path instead
+    pub fn cancel_if_unpaid(env: Env, memo: String) -> Result<(), Error> {
+        require_backend(&env)?;
+        
+        let memo = normalize_memo(&env, &memo)?;
+        let session = load_session(&env, &memo)?;
+        if !session.is_active {
+            return Err(Error::AlreadyInactive);
+        }
+        if session.paid_so_far > 0 {
+            return Err(Error::HasPartialPayment);
+        }
+        check_deactivation_lock(&env, &session)?;
+        
+        deactivate(&env, memo, session)?;
+        Ok(())
+    }
+    
+    /// Admin deactivates a session, succeeding even if it is already inactive
+    pub fn force_deactivate(env: Env, memo: String) -> Result<(), Error> {
+        require_admin(&env)?;
+        
+        let memo = normalize_memo(&env, &memo)?;
+        let session = load_session(&env, &memo)?;
+        if session.is_active {
+            deactivate(&env, memo, session)?;
+        }
+        Ok(())
+    }
+    
+    /// Backend reopens a cancelled, unexpired session (Cancelled -> Active)
+    /// The session must pass the same gates a new registration would today
+    /// Emits `react` rather than the registration topic so indexers can tell the two apart
+    pub fn reactivate_session(env: Env, memo: String) -> Result<(), Error> {
+        require_backend(&env)?;
+        
+        let memo = normalize_memo(&env, &memo)?;
+        let mut session = load_session(&env, &memo)?;
+        if session.status != SessionStatus::Cancelled {
+            return Err(Error::InvalidStatus);
+        }
+        if is_past_expiry(&env, &session) {
+            return Err(Error::SessionExpired);
+        }
+        check_registration_open(&env)?;
+        check_memo_prefix(&env, &session.memo, &session.merchant)?;
+        check_tick(&env, session.amount)?;
+        check_merchant_approved(&env, &session.merchant, session.amount)?;
+        check_merchant_capacity(&env, &session.merchant)?;
+        check_asset_accepted(&env, &session.asset, session.amount)?;
+        
+        session.set_status(SessionStatus::Active);
+        track_opened(&env, &session)?;
+        // It was counted as cancelled, not expired: it is still unexpired now
+        drop_counter(&env, Counter::Cancelled);
+        save_session(&env, &memo, &mut session);
+        
+        publish_event(&env, symbol_short!("react"), (memo, env.ledger().timestamp()));
+        Ok(())
+    }
+    
+    /// Backend deactivates an active session and removes it from storage in one call
+    /// The memo stays reserved so it can't be registered again
+    pub fn close_session(env: Env, memo: String) -> Result<(), Error> {
+        require_backend(&env)?;
+        
+        let memo = normalize_memo(&env, &memo)?;
+        let mut session = load_session(&env, &memo)?;
+        if !session.is_active {
+            return Err(Error::AlreadyInactive);
+        }
+        check_deactivation_lock(&env, &session)?;
+        
+        session.set_status(SessionStatus::Cancelled);
+        track_closed(&env, &session)?;
+        count_ended(&env, &session);
+        env.storage().persistent().remove(&session_key(&env, &memo));
+        env.storage().persistent().set(&DataKey::MemoUsed(memo.clone()), &true);
+        
+        publish_event(&env, symbol_short!("closed"), memo);
+        Ok(())
+    }
+    
+    /// Backend moves an active, unpaid session to a different merchant
+    pub fn reassign_merchant(env: Env, memo: String, new_merchant: Address) -> Result<(), Error> {
+        require_backend(&env)?;
+        
+        let memo = normalize_memo(&env, &memo)?;
+        let mut session = load_session(&env, &memo)?;
+        if !session.is_active || session.paid_so_far > 0 {
+            return Err(Error::InvalidStatus);
+        }
+        if session.merchant == new_merchant {
+            return Ok(());
+        }
+        check_merchant_approved(&env, &new_merchant, session.amount)?;
+        check_memo_prefix(&env, &session.memo, &new_merchant)?;
+        check_merchant_capacity(&env, &new_merchant)?;
+        
+        let old_merchant = session.merchant.clone();
+        track_closed(&env, &session)?;
+        session.merchant = new_merchant.clone();
+        track_opened(&env, &session)?;
+        save_session(&env, &memo, &mut session);
+        
+        publish_event(&env, symbol_short!("reassign"), (memo, old_merchant, new_merchant));
+        Ok(())
+    }
+    
+    /// Backend changes an active session's amount, only if it still equals `expected_amount`
+    /// Lets concurrent backend instances detect a lost update instead of overwriting it
+    pub fn update_amount_cas(
+        env: Env,
+        memo: String,
+        expected_amount: i128,
+        new_amount: i128,
+    ) -> Result<(), Error> {
+        require_backend(&env)?;
+        
+        let memo = normalize_memo(&env, &memo)?;
+        let mut session = load_session(&env, &memo)?;
+        if session.status != SessionStatus::Active {
+            return Err(Error::InvalidStatus);
+        }
+        if session.amount != expected_amount {
+            return Err(Error::CasMismatch);
+        }
+        let tick: i128 = setting(&env, Setting::TickSize).unwrap_or(1);
+        if new_amount <= session.paid_so_far || new_amount % tick != 0 {
+            return Err(Error::InvalidAmount);
+        }
+        check_asset_max(&env, &session.asset, new_amount)?;
+        check_merchant_approved(&env, &session.merchant, new_amount)?;
+        
+        let outstanding = total_outstanding(&env)
+            .checked_sub(session.amount)
+            .and_then(|total| total.checked_add(new_amount))
+            .ok_or(Error::Overflow)?;
+        env.storage().instance().set(&DataKey::Outstanding, &outstanding);
+        session.amount = new_amount;
+        save_session(&env, &memo, &mut session);
+        
+        publish_event(&env, symbol_short!("amount"), (memo, expected_amount, new_amount));
+        Ok(())
+    }
+    
+    /// Backend releases a held escrow payment (Held -> Completed)
+    /// Returns false if the hold window had lapsed, in which case the payment is refunded instead
+    pub fn release_escrow(env: Env, memo: String) -> Result<bool, Error> {
+        require_backend(&env)?;
+        
+        let memo = normalize_memo(&env, &memo)?;
+        let mut session = load_session(&env, &memo)?;
+        if refund_lapsed_escrow(&env, &memo, &mut session)? {
+            return Ok(false);
+        }
+        settle_escrow(&env, memo, SessionStatus::Completed, symbol_short!("release"))?;
+        Ok(true)
+    }
+    
+    /// Backend refunds held escrow payments whose hold window lapsed without a release
+    /// Returns how many were refunded; other memos are skipped
+    pub fn sweep_escrow(env: Env, memos: Vec<String>) -> Result<u32, Error> {
+        require_backend(&env)?;
+        if memos.len() > MAX_BATCH_SIZE {
+            return Err(Error::BatchTooLarge);
+        }
+        
+        let mut refunded = 0;
+        for memo in memos.iter() {
+            if let Ok(mut session) = load_session(&env, &memo) {
+                let memo = session.memo.clone();
+                if refund_lapsed_escrow(&env, &memo, &mut session)? {
+                    refunded += 1;
+                }
+            }
+        }
+        Ok(refunded)
+    }
+    
+    /// Backend refunds a held escrow payment (Held -> Refunded)
+    pub fn refund_escrow(env: Env, memo: String) -> Result<(), Error> {
+        require_backend(&env)?;
+        let memo = normalize_memo(&env, &memo)?;
+        settle_escrow(&env, memo, SessionStatus::Refunded, symbol_short!("refund"))
+    }
+    
+    /// Backend records that a completed payment was refunded off-chain (Completed -> Refunded)
+    pub fn mark_refunded(env: Env, memo: String) -> Result<(), Error> {
+        require_backend(&env)?;
+        
+        let memo = normalize_memo(&env, &memo)?;
+        let mut session = load_session(&env, &memo)?;
+        if session.status != SessionStatus::Completed {
+            return Err(Error::InvalidStatus);
+        }
+        check_refund_window(&env, &session)?;
+        
+        refund_in_full(&env, &mut session)?;
+        save_session(&env, &memo, &mut session);
+        
+        publish_event(&env, symbol_short!("refunded"), (memo, session.merchant));
+        Ok(())
+    }
+    
+    /// Backend freezes a completed payment under dispute (Completed -> Disputed)
+    /// Refunds and other changes are refused until resolve_dispute
+    pub fn open_dispute(env: Env, memo: String) -> Result<(), Error> {
+        require_backend(&env)?;
+        
+        let memo = normalize_memo(&env, &memo)?;
+        let mut session = load_session(&env, &memo)?;
+        if session.status != SessionStatus::Completed {
+            return Err(Error::InvalidStatus);
+        }
+        session.set_status(SessionStatus::Disputed);
+        save_session(&env, &memo, &mut session);
+        
+        publish_event(&env, symbol_short!("dispute"), (memo, session.merchant));
+        Ok(())
+    }
+    
+    /// Backend settles a dispute: the payment stands (Completed) or is refunded (Refunded)
+    pub fn resolve_dispute(env: Env, memo: String, in_favor_of_merchant: bool) -> Result<(), Error> {
+        require_backend(&env)?;
+        
+        let memo = normalize_memo(&env, &memo)?;
+        let mut session = load_session(&env, &memo)?;
+        if session.status != SessionStatus::Disputed {
+            return Err(Error::InvalidStatus);
+        }
+        if in_favor_of_merchant {
+            session.set_status(SessionStatus::Completed);
+        } else {
+            refund_in_full(&env, &mut session)?;
+        }
+        save_session(&env, &memo, &mut session);
+        
+        publish_event(&env, symbol_short!("resolved"), (memo, in_favor_of_merchant));
+        Ok(())
+    }
+    
+    /// Backend records part of a completed payment as refunded, deducting it from the merchant total
+    /// Refunds accumulate up to the amount paid; the session becomes Refunded once fully refunded
+    pub fn record_partial_refund(env: Env, memo: String, amount: i128) -> Result<(), Error> {
+        require_backend(&env)?;
+        
+        let memo = normalize_memo(&env, &memo)?;
+        let mut session = load_session(&env, &memo)?;
+        if session.status != SessionStatus::Completed {
+            return Err(Error::InvalidStatus);
+        }
+        if amount <= 0 {
+            return Err(Error::InvalidAmount);
+        }
+        check_refund_window(&env, &session)?;
+        
+        let paid = session.actual_amount.unwrap_or(session.amount);
+        let refunded = session.refunded_amount.checked_add(amount).ok_or(Error::Overflow)?;
+        if refunded > paid {
+            return Err(Error::RefundExceedsPaid);
+        }
+        add_merchant_total(&env, &session.merchant, -amount)?;
+        session.refunded_amount = refunded;
+        if refunded == paid {
+            session.set_status(SessionStatus::Refunded);
+        }
+        save_session(&env, &memo, &mut session);
+        
+        publish_event(&env, symbol_short!("part_rfnd"), (memo, amount, refunded));
+        Ok(())
+    }
+    
+    /// Backend authorizes a payment, reserving it for a later capture or void
+    pub fn authorize(env: Env, memo: String, amount: i128) -> Result<(), Error> {
+        require_backend(&env)?;
+        
+        let memo = normalize_memo(&env, &memo)?;
+        let mut session = load_payable_session(&env, &memo)?;
+        check_payer_allowed(&session, &None)?;
+        if amount < min_acceptable(&session)? {
+            return Err(Error::InsufficientAmount);
+        }
+        check_overpayment(&session, amount)?;
+        
+        let expires_at = env
+            .ledger()
+            .timestamp()
+            .checked_add(AUTH_WINDOW_SECONDS)
+            .ok_or(Error::Overflow)?;
+        env.storage()
+            .persistent()
+            .set(&DataKey::Authorization(memo.clone()), &Authorization { amount, expires_at });
+        session.set_status(SessionStatus::Authorized);
+        save_session(&env, &memo, &mut session);
+        track_closed(&env, &session)?;
+        
+        publish_event(&env, symbol_short!("authorize"), (memo, amount, expires_at));
+        Ok(())
+    }
+    
+    /// Backend captures an authorized payment, completing the session
+    /// Returns false if the authorization had lapsed, in which case the session is voided instead
+    pub fn capture(env: Env, memo: String) -> Result<bool, Error> {
+        require_backend(&env)?;
+        
+        let memo = normalize_memo(&env, &memo)?;
+        let (session, authorization) = load_authorized(&env, &memo)?;
+        env.storage().persistent().remove(&DataKey::Authorization(memo.clone()));
+        if env.ledger().timestamp() >= authorization.expires_at {
+            void_authorized(&env, memo, session);
+            return Ok(false);
+        }
+        
+        finish_payment(&env, memo, session, authorization.amount, None, 0)?;
+        Ok(true)
+    }
+    
+    /// Backend voids an authorized payment, cancelling the session
+    pub fn void(env: Env, memo: String) -> Result<(), Error> {
+        require_backend(&env)?;
+        
+        let memo = normalize_memo(&env, &memo)?;
+        let (session, _) = load_authorized(&env, &memo)?;
+        env.storage().persistent().remove(&DataKey::Authorization(memo.clone()));
+        void_authorized(&env, memo, session);
+        Ok(())
+    }
+    
+    /// Backend re-invokes an undelivered completion callback, returning whether it succeeded
+    pub fn retry_callback(env: Env, memo: String) -> Result<bool, Error> {
+        require_backend(&env)?;
+        
+        let memo = normalize_memo(&env, &memo)?;
+        let mut session = load_session(&env, &memo)?;
+        let paid = matches!(session.status, SessionStatus::Completed | SessionStatus::Held);
+        if !paid || session.callback.is_none() || session.callback_delivered {
+            return Err(Error::InvalidStatus);
+        }
+        
+        notify_callback(&env, &mut session);
+        save_session(&env, &memo, &mut session);
+        Ok(session.callback_delivered)
+    }
+    
+    /// Admin cancels a merchant's active sessions (e.g. when offboarding)
+    /// Handles up to MAX_CANCEL_BATCH sessions per call and returns how many remain active
+    pub fn cancel_merchant_sessions(env: Env, merchant: Address) -> Result<u32, Error> {
+        require_admin(&env)?;
+        
+        let index = Index::Merchant(merchant.clone());
+        let active = paged_len(&env, &index);
+        let memos = paged_range(&env, &index, 0, MAX_CANCEL_BATCH);
+        for memo in memos.iter() {
+            let session = load_session(&env, &memo)?;
+            deactivate(&env, memo, session)?;
+        }
+        
+        publish_event(&env, Symbol::new(&env, "cancel_all"), (merchant, memos.len()));
+        Ok(active - memos.len())
+    }
+    
+    /// Backend extends the storage TTL of up to MAX_BATCH_SIZE sessions to at least `ledgers`
+    /// Unknown memos are skipped; returns how many sessions were bumped
+    pub fn bump_sessions(env: Env, memos: Vec<String>, ledgers: u32) -> Result<u32, Error> {
+        require_backend(&env)?;
+        if memos.len() > MAX_BATCH_SIZE {
+            return Err(Error::BatchTooLarge);
+        }
+        
+        let mut bumped = 0;
+        for memo in memos.iter() {
+            let key = session_key(&env, &memo);
+            if env.storage().persistent().has(&key) {
+                env.storage().persistent().extend_ttl(&key, ledgers, ledgers);
+                bumped += 1;
+            }
+        }
+        Ok(bumped)
+    }
+    
+    /// Get session details (for frontend verification)
+    pub fn get_session(env: Env, memo: String) -> Option<PaymentSession> {
+        env.storage().persistent().get(&session_key(&env, &memo))
+    }
+    
+    /// Stored statuses for up to MAX_BATCH_SIZE memos, aligned with the input (None = no session)
+    pub fn get_statuses(env: Env, memos: Vec<String>) -> Result<Vec<Option<SessionStatus>>, Error> {
+        if memos.len() > MAX_BATCH_SIZE {
+            return Err(Error::BatchTooLarge);
+        }
+        
+        let mut statuses = Vec::new(&env);
+        for memo in memos.iter() {
+            let session: Option<PaymentSession> =
+                env.storage().persistent().get(&session_key(&env, &memo));
+            statuses.push_back(session.map(|session| session.status));
+        }
+        Ok(statuses)
+    }
+    
+    /// The merchant every existing session among the memos routes to
+    /// None if they route to different merchants or none of the memos exist
+    pub fn same_merchant(env: Env, memos: Vec<String>) -> Result<Option<Address>, Error> {
+        if memos.len() > MAX_BATCH_SIZE {
+            return Err(Error::BatchTooLarge);
+        }
+        
+        let mut shared: Option<Address> = None;
+        for memo in memos.iter() {
+            let session: Option<PaymentSession> =
+                env.storage().persistent().get(&session_key(&env, &memo));
+            if let Some(session) = session {
+                match &shared {
+                    Some(merchant) if *merchant != session.merchant => return Ok(None),
+                    _ => shared = Some(session.merchant),
+                }
+            }
+        }
+        Ok(shared)
+    }
+    
+    /// Creation, last activity, completion and expiry times of a session in one read
+    pub fn get_timestamps(env: Env, memo: String) -> Result<SessionTimestamps, Error> {
+        let session = load_session(&env, &memo)?;
+        Ok(SessionTimestamps {
+            created_at: session.created_at,
+            last_activity: session.last_activity,
+            completed_at: session.completed_at,
+            expires_at: session.expires_at,
+        })
+    }
+    
+    /// Payments received against a session: each partial, plus the validation that
+    /// completed it unless a partial already did
+    pub fn get_payment_count(env: Env, memo: String) -> Result<u32, Error> {
+        let session = load_session(&env, &memo)?;
+        let completed_by_validation =
+            session.completed_at.is_some() && session.paid_so_far < partial_target(&session)?;
+        Ok(session.partial_count + completed_by_validation as u32)
+    }
+    
+    /// Backend registration nonce: advances once per successful registration
+    /// (idempotent retries and failed calls leave it unchanged)
+    pub fn get_nonce(env: Env) -> u64 {
+        counter(&env, Counter::Nonce)
+    }
+    
+    /// Total number of sessions ever registered
+    pub fn get_session_count(env: Env) -> u64 {
+        session_count(&env)
+    }
+    
+    /// Whether the memo belongs to an active, unexpired session (cheap check for payment watchers)
+    pub fn is_awaiting_payment(env: Env, memo: String) -> bool {
+        let session: Option<PaymentSession> = env.storage().persistent().get(&session_key(&env, &memo));
+        session.is_some_and(|session| session.is_active && !is_past_expiry(&env, &session))
+    }
+    
+    /// Get session details with the status effective now (accounts for expiry without a write)
+    pub fn get_session_live(env: Env, memo: String) -> Option<SessionView> {
+        let session: PaymentSession = env.storage().persistent().get(&session_key(&env, &memo))?;
+        let status = if session.status == SessionStatus::Active && is_past_expiry(&env, &session) {
+            SessionStatus::Expired
+        } else {
+            session.status
+        };
+        Some(SessionView { session, status })
+    }
+    
+    /// Get a session registered by MEMO_HASH
+    pub fn get_session_by_hash(env: Env, memo_hash: BytesN<32>) -> Option<PaymentSession> {
+        let memo: String = env.storage().persistent().get(&DataKey::MemoHash(memo_hash))?;
+        env.storage().persistent().get(&session_key(&env, &memo))
+    }
+    
+    /// Get a session by the merchant's external order reference
+    pub fn get_session_by_ref(env: Env, external_ref: String) -> Option<PaymentSession> {
+        let memo: String = env
+            .storage()
+            .persistent()
+            .get(&DataKey::ExternalRef(external_ref))?;
+        env.storage().persistent().get(&session_key(&env, &memo))
+    }
+    
+    /// Get the session registered at a position (0-based, registration order)
+    pub fn get_session_by_index(env: Env, index: u64) -> Option<PaymentSession> {
+        let memo: String = env
+            .storage()
+            .persistent()
+            .get(&DataKey::SessionIndex(index))?;
+        env.storage().persistent().get(&session_key(&env, &memo))
+    }
+    
+    /// Completed payments from the bucket containing since_ts up to now
+    /// Only the most recent MAX_STATS_BUCKETS hourly buckets are summed
+    pub fn get_stats_since(env: Env, since_ts: u64) -> Result<WindowStats, Error> {
+        let last = env.ledger().timestamp() / STATS_BUCKET_SECONDS;
+        let first = (since_ts / STATS_BUCKET_SECONDS)
+            .max((last + 1).saturating_sub(MAX_STATS_BUCKETS));
+        
+        let mut total = WindowStats::default();
+        for bucket in first..=last {
+            let stats: Option<WindowStats> = env
+                .storage()
+                .persistent()
+                .get(&DataKey::Bucket(bucket));
+            if let Some(stats) = stats {
+                total.count = total.count.checked_add(stats.count).ok_or(Error::Overflow)?;
+                total.amount = total.amount.checked_add(stats.amount).ok_or(Error::Overflow)?;
+            }
+        }
+        Ok(total)
+    }
+    
+    /// Last MAX_RECENT_COMPLETIONS completions, oldest first
+    pub fn recent_completions(env: Env) -> Vec<CompletionRecord> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::RecentCompletions)
+            .unwrap_or(Vec::new(&env))
+    }
+    
+    /// Sum of the expected amounts of all active sessions
+    pub fn get_total_outstanding(env: Env) -> i128 {
+        total_outstanding(&env)
+    }
+    
+    /// All configured limits and the global fee
+    pub fn get_limits(env: Env) -> ContractLimits {
+        ContractLimits {
+            fee_bps: env.storage().instance().get(&DataKey::FeeBps).unwrap_or(0),
+            max_ttl_seconds: setting(&env, Setting::MaxTtl).unwrap_or(0),
+            creation_fee: setting(&env, Setting::CreationFee).unwrap_or(0),
+            tick_size: setting(&env, Setting::TickSize).unwrap_or(1),
+            max_sessions_per_merchant: setting(&env, Setting::MaxSessions).unwrap_or(0),
+            registration_cooldown_seconds: setting(&env, Setting::Cooldown).unwrap_or(0),
+            min_age_seconds: setting(&env, Setting::MinAge).unwrap_or(0),
+            extend_on_partial_seconds: setting(&env, Setting::ExtendOnPartial).unwrap_or(0),
+            partial_cooldown_seconds: setting(&env, Setting::PartialCooldown).unwrap_or(0),
+            refund_window_seconds: setting(&env, Setting::RefundWindow).unwrap_or(0),
+            deactivation_lock_seconds: setting(&env, Setting::DeactivationLock).unwrap_or(0),
+            approval_threshold: setting(&env, Setting::ApprovalThreshold).unwrap_or(0),
+            min_donation: setting(&env, Setting::MinDonation).unwrap_or(0),
+            deadman_seconds: setting(&env, Setting::Deadman).unwrap_or(0),
+        }
+    }
+    
+    /// Contract-wide counters as of now
+    pub fn get_stats(env: Env) -> ContractStats {
+        contract_stats(&env)
+    }
+    
+    /// Session counts by status: active, completed, cancelled and expired
+    pub fn get_status_counts(env: Env) -> StatusCounts {
+        StatusCounts {
+            active: active_count(&env),
+            completed: counter(&env, Counter::Completed),
+            cancelled: counter(&env, Counter::Cancelled),
+            expired: counter(&env, Counter::Expired),
+        }
+    }
+    
+    /// Admin freezes the current counters under a reporting period
+    pub fn snapshot_stats(env: Env, period_id: u64) -> Result<(), Error> {
+        require_admin(&env)?;
+        
+        let key = DataKey::Snapshot(period_id);
+        if env.storage().persistent().has(&key) {
+            return Err(Error::SnapshotExists);
+        }
+        let stats = contract_stats(&env);
+        env.storage().persistent().set(&key, &stats);
+        publish_event(&env, symbol_short!("snapshot"), (period_id, stats));
+        Ok(())
+    }
+    
+    /// Counters frozen for a reporting period
+    pub fn get_snapshot(env: Env, period_id: u64) -> Option<ContractStats> {
+        env.storage().persistent().get(&DataKey::Snapshot(period_id))
+    }
+    
+    /// Memos of active sessions expecting payment in an asset, paginated
+    /// A closing session's slot is taken by the newest one, so order isn't registration order
+    pub fn get_sessions_by_asset(env: Env, asset: Address, start: u32, limit: u32) -> Vec<String> {
+        paged_range(&env, &Index::Asset(asset), start, limit)
+    }
+    
+    /// Summaries of a merchant's active sessions from the per-merchant index, paginated
+    pub fn export_merchant(env: Env, merchant: Address, start: u32, limit: u32) -> Vec<SessionSummary> {
+        let mut summaries = Vec::new(&env);
+        for memo in paged_range(&env, &Index::Merchant(merchant), start, limit).iter() {
+            if let Ok(session) = load_session(&env, &memo) {
+                summaries.push_back(SessionSummary {
+                    memo,
+                    status: session.status,
+                    amount: session.amount,
+                    created_at: session.created_at,
+                });
+            }
+        }
+        summaries
+    }
+    
+    /// Memos of refund sessions registered against an original session
+    pub fn get_refunds_for(env: Env, original_memo: String) -> Vec<String> {
+        match normalize_memo(&env, &original_memo) {
+            Ok(original) => index_memos(&env, &DataKey::Refunds(original)),
+            Err(_) => Vec::new(&env),
+        }
+    }
+    
+    /// Get the recorded payer of a completed session (None if not completed or not payer-bound)
+    pub fn get_payer(env: Env, memo: String) -> Option<Address> {
+        let session: PaymentSession = env.storage().persistent().get(&session_key(&env, &memo))?;
+        if session.is_active {
+            return None;
+        }
+        session.payer
+    }
+    
+    /// Receipt hash of a completed session: sha256 over
+    /// (memo, merchant, amount, payer, created_at, actual_amount, completed_at)
+    pub fn attest_completion(env: Env, memo: String) -> Result<BytesN<32>, Error> {
+        let session = load_session(&env, &memo)?;
+        if session.status != SessionStatus::Completed {
+            return Err(Error::NotCompleted);
+        }
+        Ok(attestation_hash(&env, &session))
+    }
+    
+    /// The supplied memos (up to MAX_BATCH_SIZE) whose sessions were created in [from_ts, to_ts]
+    pub fn sessions_created_between(
+        env: Env,
+        memos: Vec<String>,
+        from_ts: u64,
+        to_ts: u64,
+    ) -> Result<Vec<String>, Error> {
+        if memos.len() > MAX_BATCH_SIZE {
+            return Err(Error::BatchTooLarge);
+        }
+        
+        let mut matching = Vec::new(&env);
+        for memo in memos.iter() {
+            let session: Option<PaymentSession> =
+                env.storage().persistent().get(&session_key(&env, &memo));
+            if session.is_some_and(|session| (from_ts..=to_ts).contains(&session.created_at)) {
+                matching.push_back(memo);
+            }
+        }
+        Ok(matching)
+    }
+    
+    /// The supplied memos (up to MAX_BATCH_SIZE) whose sessions took a partial payment
+    /// but expired before completing, for follow-up or refund
+    pub fn get_stuck_partials(env: Env, memos: Vec<String>) -> Result<Vec<String>, Error> {
+        if memos.len() > MAX_BATCH_SIZE {
+            return Err(Error::BatchTooLarge);
+        }
+        
+        let mut stuck = Vec::new(&env);
+        for memo in memos.iter() {
+            let session: Option<PaymentSession> =
+                env.storage().persistent().get(&session_key(&env, &memo));
+            let Some(session) = session else { continue };
+            let settled = matches!(
+                session.status,
+                SessionStatus::Completed
+                    | SessionStatus::Held
+                    | SessionStatus::Refunded
+                    | SessionStatus::Disputed
+            );
+            if session.paid_so_far > 0 && !settled && is_past_expiry(&env, &session) {
+                stuck.push_back(memo);
+            }
+        }
+        Ok(stuck)
+    }
+    
+    /// Share of the session paid so far in basis points (10000 once completed)
+    pub fn completion_bps(env: Env, memo: String) -> Result<u32, Error> {
+        let session = load_session(&env, &memo)?;
+        if session.completed_at.is_some() || session.amount <= 0 {
+            return Ok(MAX_BPS);
+        }
+        let bps = session
+            .paid_so_far
+            .checked_mul(MAX_BPS as i128)
+            .ok_or(Error::Overflow)?
+            / session.amount;
+        Ok(bps.min(MAX_BPS as i128) as u32)
+    }
+    
+    /// Smallest and largest single payment the session accepts, given its amount mode
+    /// Minimum mode without an overpayment bound reports i128::MAX as the maximum
+    pub fn get_amount_range(env: Env, memo: String) -> Result<(i128, i128), Error> {
+        let session = load_session(&env, &memo)?;
+        match session.amount_mode {
+            AmountMode::Exact => Ok((session.amount, session.amount)),
+            AmountMode::Minimum => {
+                let max = if session.max_overpayment > 0 {
+                    session.amount.saturating_add(session.max_overpayment)
+                } else {
+                    i128::MAX
+                };
+                Ok((min_acceptable(&session)?, max))
+            }
+        }
+    }
+    
+    /// Shortfall of an amount against a payable session (0 = the amount would be accepted)
+    /// Doesn't consume the session; overpayments the session refuses are AmountMismatch
+    pub fn check_amount(env: Env, memo: String, amount: i128) -> Result<i128, Error> {
+        let memo = normalize_memo(&env, &memo)?;
+        let session = load_session(&env, &memo)?;
+        if !session.is_active || is_past_expiry(&env, &session) {
+            return Err(Error::SessionExpired);
+        }
+        if env.ledger().timestamp() < session.created_at {
+            return Err(Error::SessionNotStarted);
+        }
+        
+        let minimum = min_acceptable(&session)?;
+        if amount < minimum {
+            return minimum.checked_sub(amount).ok_or(Error::Overflow);
+        }
+        check_overpayment(&session, amount)?;
+        Ok(0)
+    }
+    
+    /// Paid minus expected amount for a completed session (0 or negative = exact or under)
+    pub fn get_overpayment(env: Env, memo: String) -> Result<i128, Error> {
+        let session = load_session(&env, &memo)?;
+        let paid = session.actual_amount.ok_or(Error::NotCompleted)?;
+        paid.checked_sub(session.amount).ok_or(Error::Overflow)
+    }
+    
+    /// (exists, active, expired by time) for cheap polling
+    pub fn session_state(env: Env, memo: String) -> (bool, bool, bool) {
+        match load_session(&env, &memo) {
+            Ok(session) => (true, session.is_active, is_past_expiry(&env, &session)),
+            Err(_) => (false, false, false),
+        }
+    }
+    
+    /// Session state packed into one value for low-bandwidth clients (0 = no such session)
+    /// Bits: 0 exists, 1 active, 2 expired, 3 partially paid, 4 completed, 5 refunded
+    pub fn session_flags(env: Env, memo: String) -> u32 {
+        let session = match load_session(&env, &memo) {
+            Ok(session) => session,
+            Err(_) => return 0,
+        };
+        let mut flags = FLAG_EXISTS;
+        if session.is_active {
+            flags |= FLAG_ACTIVE;
+        }
+        if is_past_expiry(&env, &session) {
+            flags |= FLAG_EXPIRED;
+        }
+        if session.paid_so_far > 0 {
+            flags |= FLAG_PARTIAL;
+        }
+        if matches!(session.status, SessionStatus::Completed | SessionStatus::Disputed) {
+            flags |= FLAG_COMPLETED;
+        }
+        if session.status == SessionStatus::Refunded {
+            flags |= FLAG_REFUNDED;
+        }
+        flags
+    }
+    
+    /// Platform fee a merchant would pay on an amount, for previews before registering
+    pub fn preview_fee(env: Env, merchant: Address, amount: i128) -> Result<i128, Error> {
+        fee_for(&env, &merchant, None, amount)
+    }
+    
+    /// Fee rate a session without its own fee_bps would pay: 0 for exempt merchants,
+    /// else the fee tier the amount meets, else the global rate
+    pub fn effective_fee_bps(env: Env, merchant: Address, amount: i128) -> u32 {
+        if env.storage().persistent().has(&DataKey::FeeExempt(merchant)) {
+            return 0;
+        }
+        applicable_fee_bps(&env, None, amount)
+    }
+    
+    /// What the merchant nets from paying the session's amount, after the applicable fee
+    pub fn net_payout(env: Env, memo: String) -> Result<i128, Error> {
+        let session = load_session(&env, &memo)?;
+        let fee = compute_fee(&env, &session, session.amount)?;
+        session.amount.checked_sub(fee).ok_or(Error::Overflow)
+    }
+    
+    /// Every error code paired with its name, for client error maps
+    pub fn error_catalog(env: Env) -> Vec<(u32, Symbol)> {
+        let mut catalog = Vec::new(&env);
+        for (code, name) in ERROR_CATALOG {
+            catalog.push_back((*code, Symbol::new(&env, name)));
+        }
+        catalog
+    }
+    
+    /// Check that a memo is an active, unexpired session for the expected merchant and at least min_amount
+    pub fn verify_session(
+        env: Env,
+        memo: String,
+        expected_merchant: Address,
+        min_amount: i128,
+    ) -> Result<bool, Error> {
+        let session = load_session(&env, &memo)?;
+        if !session.is_active || is_past_expiry(&env, &session) {
+            return Err(Error::SessionExpired);
+        }
+        Ok(session.merchant == expected_merchant && session.amount >= min_amount)
+    }
+    
+    /// Merchants with at least one active session, capped at MAX_ACTIVE_MERCHANTS
+    /// Returns the merchants and whether the list was truncated
+    pub fn get_active_merchants(env: Env) -> (Vec<Address>, bool) {
+        let merchants = index_page_at(&env, &Index::ActiveMerchants, 0);
+        (merchants, paged_len(&env, &Index::ActiveMerchants) > MAX_ACTIVE_MERCHANTS)
+    }
+    
+    /// Read-only sanity check of tracked counters, for monitoring canaries
+    /// Per-merchant consistency is only checked while the active-merchant list fits in one page
+    pub fn check_invariants(env: Env) -> bool {
+        let active = active_count(&env);
+        if active > session_count(&env) {
+            return false;
+        }
+        
+        let listed = paged_len(&env, &Index::ActiveMerchants);
+        if listed as u64 > active {
+            return false;
+        }
+        if listed > MAX_ACTIVE_MERCHANTS {
+            return true;
+        }
+        
+        let merchants: Vec<Address> = index_page_at(&env, &Index::ActiveMerchants, 0);
+        let mut sum: u64 = 0;
+        for merchant in merchants.iter() {
+            let count = merchant_active_count(&env, &merchant);
+            let indexed = paged_len(&env, &Index::Merchant(merchant));
+            if count == 0 || count != indexed {
+                return false;
+            }
+            sum += count as u64;
+        }
+        sum == active
+    }
+    
+    /// Whether a payer has completed at least one payer-bound payment to a merchant
+    pub fn has_paid(env: Env, merchant: Address, payer: Address) -> bool {
+        env.storage().persistent().has(&DataKey::PaidBy(merchant, payer))
+    }
+    
+    /// Number of sessions a merchant currently has active
+    pub fn get_active_session_count(env: Env, merchant: Address) -> u32 {
+        merchant_active_count(&env, &merchant)
+    }
+    
+    /// Assets a merchant has completed payments in, in first-seen order
+    pub fn get_merchant_assets(env: Env, merchant: Address) -> Vec<Address> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::MerchantAssets(merchant))
+            .unwrap_or(Vec::new(&env))
+    }
+    
+    /// Total amount of completed payments for a merchant, net of refunds
+    pub fn get_merchant_total(env: Env, merchant: Address) -> i128 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::MerchantTotal(merchant))
+            .unwrap_or(0)
+    }
+    
+    /// Totals for several merchants, aligned with the input (0 for unseen merchants)
+    pub fn get_merchant_totals(env: Env, merchants: Vec<Address>) -> Result<Vec<i128>, Error> {
+        if merchants.len() > MAX_BATCH_SIZE {
+            return Err(Error::BatchTooLarge);
+        }
+        
+        let mut totals = Vec::new(&env);
+        for merchant in merchants.iter() {
+            totals.push_back(Self::get_merchant_total(env.clone(), merchant));
+        }
+        Ok(totals)
+    }
+    
+    /// Creation fees accrued by a merchant since the last billing reset
+    pub fn get_accrued_fees(env: Env, merchant: Address) -> i128 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::Accrued(merchant))
+            .unwrap_or(0)
+    }
+    
+    /// Backend clears a merchant's accrued creation fees after billing them off-chain
+    pub fn reset_accrued_fees(env: Env, merchant: Address) -> Result<(), Error> {
+        require_backend(&env)?;
+        
+        env.storage()
+            .persistent()
+            .remove(&DataKey::Accrued(merchant.clone()));
+        
+        publish_event(&env, symbol_short!("fee_reset"), merchant);
+        Ok(())
+    }
+    
+    /// Stored backend, admin and any pending admin transfer
+    pub fn get_controllers(env: Env) -> Result<Controllers, Error> {
+        let storage = env.storage().instance();
+        Ok(Controllers {
+            backend: storage.get(&DataKey::Backend).ok_or(Error::Unauthorized)?,
+            admin: storage.get(&DataKey::Admin),
+            pending_admin: storage.get(&DataKey::PendingAdmin),
+        })
+    }
+    
+    /// Roles an address holds: bit 0 backend, 1 admin, 2 approved merchant
+    pub fn role_of(env: Env, addr: Address) -> u32 {
+        let storage = env.storage().instance();
+        let mut roles = 0;
+        if storage.get::<_, Address>(&DataKey::Backend) == Some(addr.clone()) {
+            roles |= ROLE_BACKEND;
+        }
+        if storage.get::<_, Address>(&DataKey::Admin) == Some(addr.clone()) {
+            roles |= ROLE_ADMIN;
+        }
+        if env.storage().persistent().has(&DataKey::ApprovedMerchant(addr)) {
+            roles |= ROLE_MERCHANT;
+        }
+        roles
+    }
+    
+    /// Admin proposes a new admin, who must call accept_admin to take over
+    pub fn transfer_admin(env: Env, new_admin: Address) -> Result<(), Error> {
+        require_admin(&env)?;
+        
+        env.storage().instance().set(&DataKey::PendingAdmin, &new_admin);
+        publish_event(&env, symbol_short!("adm_prop"), new_admin);
+        Ok(())
+    }
+    
+    /// Admin replaces the backend address
+    pub fn set_backend(env: Env, backend: Address) -> Result<(), Error> {
+        require_config_admin(&env)?;
+        
+        env.storage().instance().set(&DataKey::Backend, &backend);
+        publish_event(&env, symbol_short!("backend"), backend);
+        Ok(())
+    }
+    
+    /// Admin permanently freezes configuration: backend, fees, limits, assets and pausing
+    /// Sessions keep registering and validating; admin transfer and merchant approvals stay open
+    pub fn seal(env: Env) -> Result<(), Error> {
+        require_config_admin(&env)?;
+        
+        env.storage().instance().set(&DataKey::Sealed, &true);
+        publish_event(&env, symbol_short!("sealed"), ());
+        Ok(())
+    }
+    
+    /// Admin clears a tripped dead-man switch, restarting the backend silence window
+    pub fn reset_deadman(env: Env) -> Result<(), Error> {
+        require_admin(&env)?;
+        
+        env.storage().instance().set(&DataKey::LastBackendCall, &env.ledger().timestamp());
+        publish_event(&env, symbol_short!("dm_reset"), ());
+        Ok(())
+    }
+    
+    /// Whether seal has been called
+    pub fn is_sealed(env: Env) -> bool {
+        env.storage().instance().has(&DataKey::Sealed)
+    }
+    
+    /// Pending admin accepts the transfer and becomes admin
+    pub fn accept_admin(env: Env) -> Result<(), Error> {
+        let pending: Address = env
+            .storage()
+            .instance()
+            .get(&DataKey::PendingAdmin)
+            .ok_or(Error::Unauthorized)?;
+        pending.require_auth();
+        
+        env.storage().instance().set(&DataKey::Admin, &pending);
+        env.storage().instance().remove(&DataKey::PendingAdmin);
+        publish_event(&env, symbol_short!("adm_set"), pending);
+        Ok(())
+    }
+    
+    /// Admin sets how many active sessions a merchant may have (0 = unlimited)
+    pub fn set_max_sessions_per_merchant(env: Env, max: u32) -> Result<(), Error> {
+        require_config_admin(&env)?;
+        
+        set_setting(&env, Setting::MaxSessions, &max);
+        publish_event(&env, symbol_short!("max_sess"), max);
+        Ok(())
+    }
+    
+    /// Admin stops new registrations; existing sessions can still be paid
+    pub fn pause_registration(env: Env) -> Result<(), Error> {
+        require_config_admin(&env)?;
+        env.storage().instance().set(&DataKey::RegistrationPaused, &true);
+        publish_event(&env, symbol_short!("reg_pause"), true);
+        Ok(())
+    }
+    
+    /// Admin resumes registrations
+    pub fn unpause_registration(env: Env) -> Result<(), Error> {
+        require_config_admin(&env)?;
+        env.storage().instance().remove(&DataKey::RegistrationPaused);
+        publish_event(&env, symbol_short!("reg_pause"), false);
+        Ok(())
+    }
+    
+    /// Admin sets the platform fee applied to sessions without their own fee
+    pub fn set_global_fee_bps(env: Env, bps: u32) -> Result<(), Error> {
+        require_config_admin(&env)?;
+        if bps > MAX_BPS {
+            return Err(Error::InvalidBps);
+        }
+        
+        env.storage().instance().set(&DataKey::FeeBps, &bps);
+        publish_event(&env, symbol_short!("fee_set"), bps);
+        Ok(())
+    }
+    
+    /// Admin sets the tiered fee schedule (ascending min_amount); an empty schedule disables tiers
+    /// Tiers apply to sessions without their own fee and take precedence over the global fee
+    pub fn set_fee_schedule(env: Env, tiers: Vec<FeeTier>) -> Result<(), Error> {
+        require_config_admin(&env)?;
+        if tiers.len() > MAX_FEE_TIERS {
+            return Err(Error::InvalidFeeSchedule);
+        }
+        
+        let mut previous: Option<i128> = None;
+        for tier in tiers.iter() {
+            if tier.bps > MAX_BPS {
+                return Err(Error::InvalidBps);
+            }
+            if previous.is_some_and(|min_amount| tier.min_amount <= min_amount) {
+                return Err(Error::InvalidFeeSchedule);
+            }
+            previous = Some(tier.min_amount);
+        }
+        
+        env.storage().instance().set(&DataKey::FeeTiers, &tiers);
+        publish_event(&env, symbol_short!("fee_tiers"), tiers.len());
+        Ok(())
+    }
+    
+    /// Admin exempts a merchant from platform fees (overrides session and global fees)
+    pub fn set_fee_exempt(env: Env, merchant: Address, exempt: bool) -> Result<(), Error> {
+        require_config_admin(&env)?;
+        
+        let key = DataKey::FeeExempt(merchant.clone());
+        if exempt {
+            env.storage().persistent().set(&key, &true);
+        } else {
+            env.storage().persistent().remove(&key);
+        }
+        publish_event(&env, symbol_short!("fee_exmpt"), (merchant, exempt));
+        Ok(())
+    }
+    
+    /// Admin sets the merchant memo-less donations are credited to
+    pub fn set_default_merchant(env: Env, merchant: Address) -> Result<(), Error> {
+        require_config_admin(&env)?;
+        env.storage().instance().set(&DataKey::DefaultMerchant, &merchant);
+        publish_event(&env, symbol_short!("def_mer"), merchant);
+        Ok(())
+    }
+    
+    /// Admin approves a merchant, turning on the merchant allowlist if it was off
+    pub fn approve_merchant(env: Env, merchant: Address) -> Result<(), Error> {
+        require_admin(&env)?;
+        approve(&env, &merchant);
+        publish_event(&env, symbol_short!("mer_appr"), merchant);
+        Ok(())
+    }
+    
+    /// Admin approves up to MAX_BATCH_SIZE merchants at once, returning how many were new
+    pub fn approve_merchants(env: Env, merchants: Vec<Address>) -> Result<u32, Error> {
+        require_admin(&env)?;
+        if merchants.len() > MAX_BATCH_SIZE {
+            return Err(Error::BatchTooLarge);
+        }
+        
+        let mut added = 0;
+        for merchant in merchants.iter() {
+            if !env.storage().persistent().has(&DataKey::ApprovedMerchant(merchant.clone())) {
+                approve(&env, &merchant);
+                added += 1;
+            }
+        }
+        publish_event(&env, symbol_short!("mer_apprs"), added);
+        Ok(added)
+    }
+    
+    /// Admin revokes a merchant's approval; its existing sessions are unaffected
+    pub fn revoke_merchant(env: Env, merchant: Address) -> Result<(), Error> {
+        require_admin(&env)?;
+        env.storage().persistent().remove(&DataKey::ApprovedMerchant(merchant.clone()));
+        publish_event(&env, symbol_short!("mer_revk"), merchant);
+        Ok(())
+    }
+    
+    /// Admin adds a token contract to the accepted-asset allowlist
+    pub fn add_asset(env: Env, asset: Address) -> Result<(), Error> {
+        require_config_admin(&env)?;
+        
+        let mut assets = accepted_assets(&env);
+        if !assets.contains(&asset) {
+            if assets.len() >= MAX_ACCEPTED_ASSETS {
+                return Err(Error::TooManyAssets);
+            }
+            assets.push_back(asset.clone());
+            env.storage().instance().set(&DataKey::Assets, &assets);
+        }
+        
+        publish_event(&env, symbol_short!("asset_add"), asset);
+        Ok(())
+    }
+    
+    /// Accepted-asset allowlist (empty = any asset accepted), at most MAX_ACCEPTED_ASSETS entries
+    pub fn get_accepted_assets(env: Env) -> Vec<Address> {
+        accepted_assets(&env)
+    }
+    
+    /// Admin removes a token contract from the accepted-asset allowlist
+    pub fn remove_asset(env: Env, asset: Address) -> Result<(), Error> {
+        require_config_admin(&env)?;
+        
+        let mut assets = accepted_assets(&env);
+        if let Some(index) = assets.first_index_of(&asset) {
+            assets.remove(index);
+            env.storage().instance().set(&DataKey::Assets, &assets);
+        }
+        
+        publish_event(&env, symbol_short!("asset_rm"), asset);
+        Ok(())
+    }
+    
+    /// Platform fees earned in an asset since the last reset (affiliate-routed fees excluded)
+    pub fn get_platform_fees(env: Env, asset: Address) -> i128 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::PlatformFees(asset))
+            .unwrap_or(0)
+    }
+    
+    /// Admin clears an asset's accumulated platform fees after paying them out
+    pub fn reset_platform_fees(env: Env, asset: Address) -> Result<(), Error> {
+        require_admin(&env)?;
+        
+        let paid_out = Self::get_platform_fees(env.clone(), asset.clone());
+        env.storage().persistent().remove(&DataKey::PlatformFees(asset.clone()));
+        publish_event(&env, symbol_short!("fee_reset"), (asset, paid_out));
+        Ok(())
+    }
+    
+    /// Admin caps the amount of sessions registered in an asset (0 removes the cap)
+    pub fn set_asset_max(env: Env, asset: Address, max: i128) -> Result<(), Error> {
+        require_config_admin(&env)?;
+        if max < 0 {
+            return Err(Error::InvalidAmount);
+        }
+        
+        let key = DataKey::AssetMax(asset.clone());
+        if max > 0 {
+            env.storage().persistent().set(&key, &max);
+        } else {
+            env.storage().persistent().remove(&key);
+        }
+        publish_event(&env, symbol_short!("asset_max"), (asset, max));
+        Ok(())
+    }
+}
+
+/// Validate and store a new session under a caller-chosen memo (callers check authorization)
+fn register(
+    env: &Env,
+    memo: String,
+    merchant: Address,
+    amount: i128,
+    options: SessionOptions,
+) -> Result<PaymentSession, Error> {
+    check_memo_unreserved(&normalize_memo(env, &memo)?)?;
+    create_session(env, memo, merchant, amount, options, true)
+}
+
+/// Create a session; `merchant_gates` applies the checks that throttle merchant registrations
+/// (registration pause, memo prefix, session cap, cooldown), which contract-created sessions skip
+fn create_session(
+    env: &Env,
+    memo: String,
+    merchant: Address,
+    amount: i128,
+    options: SessionOptions,
+    merchant_gates: bool,
+) -> Result<PaymentSession, Error> {
+    // A retried registration gets the session its first attempt created, as long as it
+    // asks for the same merchant and amount
+    if let Some(key) = &options.idempotency_key {
+        let first: Option<String> =
+            env.storage().persistent().get(&DataKey::Idempotency(key.clone()));
+        if let Some(session) = first.and_then(|memo| {
+            env.storage().persistent().get::<_, PaymentSession>(&DataKey::Session(memo))
+        }) {
+            if session.merchant != merchant || session.amount != amount {
+                return Err(Error::SessionAlreadyExists);
+            }
+            return Ok(session);
+        }
+    }
+    
+    if merchant_gates {
+        check_registration_open(env)?;
+    }
+    
+    // Store under the canonical memo so cosmetic variants can't register twice
+    let memo = normalize_memo(env, &memo)?;
+    if env.storage().persistent().has(&DataKey::Session(memo.clone()))
+        || env.storage().persistent().has(&DataKey::MemoUsed(memo.clone()))
+    {
+        return Err(Error::SessionAlreadyExists);
+    }
+    
+    if merchant_gates {
+        check_memo_prefix(env, &memo, &merchant)?;
+    }
+    
+    if let Some(bps) = options.fee_bps {
+        if bps > MAX_BPS {
+            return Err(Error::InvalidBps);
+        }
+    }
+    if options.completion_threshold_bps > MAX_BPS
+        || options.min_partial_bps > MAX_BPS
+        || options.tolerance_bps > MAX_BPS
+    {
+        return Err(Error::InvalidBps);
+    }
+    if options.priority > MAX_PRIORITY {
+        return Err(Error::InvalidPriority);
+    }
+    if let Some(code) = &options.fiat_code {
+        if code.is_empty() || code.len() > MAX_FIAT_CODE_LEN {
+            return Err(Error::InvalidFiatCode);
+        }
+    }
+    
+    // Outstanding totals sum expected amounts, so each must be positive
+    if amount <= 0 {
+        return Err(Error::InvalidAmount);
+    }
+    check_tick(env, amount)?;
+    check_merchant_approved(env, &merchant, amount)?;
+    if merchant_gates {
+        check_merchant_capacity(env, &merchant)?;
+    }
+    
+    // Per-merchant cooldown between registrations
+    let cooldown: u64 = if merchant_gates {
+        setting(env, Setting::Cooldown).unwrap_or(0)
+    } else {
+        0
+    };
+    let last_reg_key = DataKey::LastRegistration(merchant.clone());
+    if cooldown > 0 {
+        let last: Option<u64> = env.storage().persistent().get(&last_reg_key);
+        if let Some(last) = last {
+            if env.ledger().timestamp() < last.saturating_add(cooldown) {
+                return Err(Error::RateLimited);
+            }
+        }
+    }
+    
+    // A refund must point at an existing original session
+    let refund_of = match &options.refund_of {
+        Some(original) => {
+            let original = normalize_memo(env, original)?;
+            load_session(env, &original)?;
+            Some(original)
+        }
+        None => None,
+    };
+    
+    let allowed_payers = options.allowed_payers.clone().unwrap_or(Vec::new(env));
+    if allowed_payers.len() > MAX_ALLOWED_PAYERS {
+        return Err(Error::TooManyPayers);
+    }
+    
+    // External references map to a single session
+    if let Some(external_ref) = &options.external_ref {
+        if env.storage().persistent().has(&DataKey::ExternalRef(external_ref.clone())) {
+            return Err(Error::DuplicateReference);
+        }
+    }
+    
+    check_asset_accepted(env, &options.asset, amount)?;
+    
+    // Lifetime is capped by the configured max TTL
+    let max_ttl: u64 = setting(env, Setting::MaxTtl).unwrap_or(0);
+    if max_ttl > 0 && options.ttl_seconds > max_ttl {
+        return Err(Error::TtlTooLong);
+    }
+    let ttl = if options.ttl_seconds == 0 { max_ttl } else { options.ttl_seconds };
+    
+    // Get current ledger timestamp
+    let created_at = env.ledger().timestamp();
+    let expires_at = if ttl > 0 {
+        Some(created_at.checked_add(ttl).ok_or(Error::Overflow)?)
+    } else {
+        None
+    };
+    
+    // Store session data
+    let session = PaymentSession {
+        memo: memo.clone(),
+        merchant,
+        amount,
+        is_active: true,
+        created_at,
+        asset: options.asset,
+        payer: None,
+        fee_bps: options.fee_bps,
+        fee: 0,
+        status: SessionStatus::Active,
+        escrow: options.escrow,
+        expires_at,
+        amount_mode: options.amount_mode,
+        max_overpayment: options.max_overpayment,
+        paid_so_far: 0,
+        partial_count: 0,
+        max_partials: if options.max_partials == 0 {
+            DEFAULT_MAX_PARTIALS
+        } else {
+            options.max_partials
+        },
+        require_customer_auth: options.require_customer_auth,
+        refund_of: refund_of.clone(),
+        completion_threshold_bps: if options.completion_threshold_bps == 0 {
+            MAX_BPS
+        } else {
+            options.completion_threshold_bps
+        },
+        completed_at: None,
+        actual_amount: None,
+        min_confirmations: options.min_confirmations,
+        confirmations_at_validation: 0,
+        external_ref: options.external_ref.clone(),
+        allowed_payers,
+        allow_partial: options.allow_partial.unwrap_or(true),
+        last_activity: created_at,
+        min_partial_bps: options.min_partial_bps,
+        callback: options.callback,
+        callback_attempts: 0,
+        callback_delivered: false,
+        tolerance_bps: options.tolerance_bps,
+        priority: options.priority,
+        fee_recipient: options.fee_recipient,
+        fiat_code: options.fiat_code.clone(),
+        refunded_amount: 0,
+        hold_expires_at: None,
+        require_merchant_auth: options.require_merchant_auth,
+    };
+    
+    env.storage().persistent().set(&DataKey::Session(memo.clone()), &session);
+    if cooldown > 0 {
+        env.storage().persistent().set(&last_reg_key, &created_at);
+    }
+    accrue_creation_fee(env, &session.merchant)?;
+    append_session_index(env, &memo);
+    track_opened(env, &session)?;
+    if let Some(original) = refund_of {
+        index_push(env, &DataKey::Refunds(original), &memo);
+    }
+    if let Some(external_ref) = options.external_ref {
+        env.storage().persistent().set(&DataKey::ExternalRef(external_ref), &memo);
+    }
+    if let Some(key) = options.idempotency_key {
+        env.storage().persistent().set(&DataKey::Idempotency(key), &memo);
+    }
+    bump_counter(env, Counter::Nonce);
+    
+    // Emit event
+    publish_event(env, registration_topic(env), memo);
+    
+    Ok(session)
+}
+
+/// Internal memo for a hash-memo session: `h_` plus the hash's leading bytes in hex
+fn hash_memo(env: &Env, memo_hash: &BytesN<32>) -> String {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    let hash = memo_hash.to_array();
+    let mut memo = [0u8; 2 + 2 * HASH_MEMO_BYTES];
+    memo[0] = b'h';
+    memo[1] = b'_';
+    for (i, byte) in hash[..HASH_MEMO_BYTES].iter().enumerate() {
+        memo[2 + 2 * i] = HEX[(byte >> 4) as usize];
+        memo[3 + 2 * i] = HEX[(byte & 0x0f) as usize];
+    }
+    String::from_bytes(env, &memo)
+}
+
+/// Internal memo for the n-th donation: `don_` plus n in decimal
+fn donation_memo(env: &Env, n: u64) -> String {
+    // u64::MAX has 20 digits, written right to left after the 4-byte prefix
+    let mut memo = [0u8; 24];
+    memo[..4].copy_from_slice(b"don_");
+    let mut start = memo.len();
+    let mut n = n;
+    loop {
+        start -= 1;
+        memo[start] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    memo.copy_within(start.., 4);
+    String::from_bytes(env, &memo[..4 + memo.len() - start])
+}
+
+/// Payment reference for a session: `chainpe:<memo>:<amount>`, with the canonical memo and
+/// the amount in base units as a decimal integer. Clients parse this, so keep it stable
+fn payment_link(env: &Env, memo: &String, amount: i128) -> String {
+    const PREFIX: &[u8] = b"chainpe:";
+    // Prefix, memo, separator and the 39 digits of u128::MAX
+    let mut link = [0u8; 8 + MAX_MEMO_LEN + 1 + 39];
+    let mut len = PREFIX.len();
+    link[..len].copy_from_slice(PREFIX);
+    let memo_len = memo.len() as usize;
+    memo.copy_into_slice(&mut link[len..len + memo_len]);
+    len += memo_len;
+    link[len] = b':';
+    len += 1;
+    
+    // Registered amounts are always positive
+    let mut digits = [0u8; 39];
+    let mut start = digits.len();
+    let mut n = amount as u128;
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    let digits = &digits[start..];
+    link[len..len + digits.len()].copy_from_slice(digits);
+    String::from_bytes(env, &link[..len + digits.len()])
+}
+
+/// Add a merchant to the allowlist and turn the allowlist on
+fn approve(env: &Env, merchant: &Address) {
+    env.storage().instance().set(&DataKey::MerchantAllowlist, &true);
+    env.storage().persistent().set(&DataKey::ApprovedMerchant(merchant.clone()), &true);
+}
+
+/// Check a payment against its session and mark the session completed
+fn complete_payment(
+    env: &Env,
+    memo: String,
+    amount: i128,
+    payer: Option<Address>,
+    confirmations: u32,
+) -> Result<bool, Error> {
+    check_schema(env)?;
+    check_deadman(env)?;
+    
+    // Look up by the same canonical form used at registration
+    let memo = normalize_memo(env, &memo)?;
+    
+    // A retry by the recorded payer is idempotent while the payment stands; anyone else
+    // can't claim the session
+    if let Some(payer) = &payer {
+        let session = load_session(env, &memo)?;
+        if let Some(recorded) = &session.payer {
+            if recorded != payer {
+                return Err(Error::AlreadyCompleted);
+            }
+            if !matches!(session.status, SessionStatus::Completed | SessionStatus::Held) {
+                return Err(Error::InvalidStatus);
+            }
+            return Ok(true);
+        }
+    }
+    
+    let session = load_payable_session(env, &memo)?;
+    check_payer_allowed(&session, &payer)?;
+    
+    // Check if amount is sufficient
+    if amount < min_acceptable(&session)? {
+        publish_event(env, symbol_short!("insuff"), (memo.clone(), amount, session.amount));
+        return Err(Error::InsufficientAmount);
+    }
+    check_overpayment(&session, amount)?;
+    
+    finish_payment(env, memo, session, amount, payer, confirmations)?;
+    Ok(true)
+}
+
+/// Restricted sessions only accept payments from a listed payer, so they can't
+/// complete through a path that doesn't name one
+fn check_payer_allowed(session: &PaymentSession, payer: &Option<Address>) -> Result<(), Error> {
+    if !session.allowed_payers.is_empty()
+        && !payer.as_ref().is_some_and(|payer| session.allowed_payers.contains(payer))
+    {
+        return Err(Error::WrongPayer);
+    }
+    Ok(())
+}
+
+/// New sessions can't open while registration is paused
+fn check_registration_open(env: &Env) -> Result<(), Error> {
+    if env.storage().instance().has(&DataKey::RegistrationPaused) {
+        return Err(Error::ContractPaused);
+    }
+    Ok(())
+}
+
+/// Amount must sit on the configured tick
+fn check_tick(env: &Env, amount: i128) -> Result<(), Error> {
+    let tick: i128 = setting(env, Setting::TickSize).unwrap_or(1);
+    if amount % tick != 0 {
+        return Err(Error::InvalidAmount);
+    }
+    Ok(())
+}
+
+/// Per-merchant cap on concurrently active sessions
+fn check_merchant_capacity(env: &Env, merchant: &Address) -> Result<(), Error> {
+    let max_sessions: u32 = setting(env, Setting::MaxSessions).unwrap_or(0);
+    if max_sessions > 0 && merchant_active_count(env, merchant) >= max_sessions {
+        return Err(Error::MerchantLimitReached);
+    }
+    Ok(())
+}
+
+/// Asset must be on the allowlist (an empty allowlist accepts any asset) and within its maximum
+fn check_asset_accepted(env: &Env, asset: &Option<Address>, amount: i128) -> Result<(), Error> {
+    if let Some(asset) = asset {
+        let assets = accepted_assets(env);
+        if !assets.is_empty() && !assets.contains(asset) {
+            return Err(Error::AssetNotAllowed);
+        }
+    }
+    check_asset_max(env, asset, amount)
+}
+
+/// Amounts in an asset can't exceed its configured maximum
+fn check_asset_max(env: &Env, asset: &Option<Address>, amount: i128) -> Result<(), Error> {
+    if let Some(asset) = asset {
+        let max: Option<i128> = env.storage().persistent().get(&DataKey::AssetMax(asset.clone()));
+        if max.is_some_and(|max| amount > max) {
+            return Err(Error::InvalidAmount);
+        }
+    }
+    Ok(())
+}
+
+/// Once the merchant allowlist is on, only approved merchants can hold
+/// sessions at or above the approval threshold
+fn check_merchant_approved(env: &Env, merchant: &Address, amount: i128) -> Result<(), Error> {
+    let approval_threshold: i128 = setting(env, Setting::ApprovalThreshold).unwrap_or(0);
+    if env.storage().instance().has(&DataKey::MerchantAllowlist)
+        && amount >= approval_threshold
+        && !env.storage().persistent().has(&DataKey::ApprovedMerchant(merchant.clone()))
+    {
+        return Err(Error::MerchantNotApproved);
+    }
+    Ok(())
+}
+
+/// Load a session that can currently accept payment
+fn load_payable_session(env: &Env, memo: &String) -> Result<PaymentSession, Error> {
+    let session = load_session(env, memo)?;
+    
+    // A duplicate validation isn't an expiry
+    if matches!(
+        session.status,
+        SessionStatus::Completed | SessionStatus::Held | SessionStatus::Disputed
+    ) {
+        return Err(Error::AlreadyCompleted);
+    }
+    
+    // Check if session is active and within its lifetime
+    if !session.is_active || is_past_expiry(env, &session) {
+        publish_event(env, symbol_short!("expired"), memo.clone());
+        return Err(Error::SessionExpired);
+    }
+    
+    // Guard against sessions dated after the current ledger time
+    if env.ledger().timestamp() < session.created_at {
+        return Err(Error::SessionNotStarted);
+    }
+    
+    // Payments landing right after registration may be front-running it
+    let min_age: u64 = setting(env, Setting::MinAge).unwrap_or(0);
+    if env.ledger().timestamp() < session.created_at.saturating_add(min_age) {
+        return Err(Error::SessionTooNew);
+    }
+    
+    Ok(session)
+}
+
+/// Mark an accepted payment's session completed (or held) and emit the completion event
+fn finish_payment(
+    env: &Env,
+    memo: String,
+    mut session: PaymentSession,
+    amount: i128,
+    payer: Option<Address>,
+    confirmations: u32,
+) -> Result<(), Error> {
+    check_payer_allowed(&session, &payer)?;
+    
+    // Customer-auth sessions are bound to the payer's signature
+    if session.require_customer_auth {
+        payer.as_ref().ok_or(Error::Unauthorized)?.require_auth();
+    }
+    // and merchant-auth sessions to the merchant's
+    if session.require_merchant_auth {
+        session.merchant.require_auth();
+    }
+    
+    let fee = compute_fee(env, &session, amount)?;
+    if let (Some(asset), None, true) = (&session.asset, &session.fee_recipient, fee > 0) {
+        add_platform_fee(env, asset, fee)?;
+    }
+    
+    // Captured authorizations already left the active indexes
+    let was_active = session.is_active;
+    
+    // Mark session as completed (or held for escrow) and record the payer in the same write
+    session.set_status(if session.escrow {
+        SessionStatus::Held
+    } else {
+        SessionStatus::Completed
+    });
+    session.payer = payer.clone();
+    session.fee = fee;
+    session.completed_at = Some(env.ledger().timestamp());
+    session.actual_amount = Some(amount);
+    session.confirmations_at_validation = confirmations;
+    if session.escrow {
+        let hold_expires_at = env
+            .ledger()
+            .timestamp()
+            .checked_add(HOLD_WINDOW_SECONDS)
+            .ok_or(Error::Overflow)?;
+        session.hold_expires_at = Some(hold_expires_at);
+    }
+    notify_callback(env, &mut session);
+    save_session(env, &memo, &mut session);
+    if was_active {
+        track_closed(env, &session)?;
+    }
+    record_bucket_stats(env, amount)?;
+    record_totals(env, amount, fee)?;
+    record_recent_completion(env, &session, amount);
+    if let Some(payer) = &payer {
+        env.storage()
+            .persistent()
+            .set(&DataKey::PaidBy(session.merchant.clone(), payer.clone()), &true);
+    }
+    add_merchant_total(env, &session.merchant, amount)?;
+    if let Some(asset) = &session.asset {
+        record_merchant_asset(env, &session.merchant, asset);
+    }
+    
+    // Emit success event (escrow sessions announce the hold instead)
+    let topic = if session.escrow {
+        symbol_short!("held")
+    } else {
+        completion_topic(env)
+    };
+    let receipt = attestation_hash(env, &session);
+    let event = CompletionEvent {
+        memo,
+        merchant: session.merchant,
+        amount: session.amount,
+        actual_amount: amount,
+        payer,
+        fee,
+        ledger_seq: env.ledger().sequence(),
+        confirmations,
+        receipt,
+        priority: session.priority,
+        fee_recipient: session.fee_recipient,
+        fiat_code: session.fiat_code,
+    };
+    publish_event(env, topic, event);
+    
+    Ok(())
+}
+
+/// Add a completed payment to the merchant's running total
+fn add_merchant_total(env: &Env, merchant: &Address, amount: i128) -> Result<(), Error> {
+    let key = DataKey::MerchantTotal(merchant.clone());
+    let total: i128 = env.storage().persistent().get(&key).unwrap_or(0);
+    let total = total.checked_add(amount).ok_or(Error::Overflow)?;
+    env.storage().persistent().set(&key, &total);
+    Ok(())
+}
+
+/// Append a completion to the recent-completions buffer, evicting the oldest when full
+fn record_recent_completion(env: &Env, session: &PaymentSession, amount: i128) {
+    let key = DataKey::RecentCompletions;
+    let mut recent: Vec<CompletionRecord> =
+        env.storage().persistent().get(&key).unwrap_or(Vec::new(env));
+    if recent.len() >= MAX_RECENT_COMPLETIONS {
+        recent.pop_front();
+    }
+    recent.push_back(CompletionRecord {
+        memo: session.memo.clone(),
+        merchant: session.merchant.clone(),
+        amount,
+        timestamp: env.ledger().timestamp(),
+    });
+    env.storage().persistent().set(&key, &recent);
+}
+
+/// Add to the platform's unpaid fees in an asset
+fn add_platform_fee(env: &Env, asset: &Address, fee: i128) -> Result<(), Error> {
+    let key = DataKey::PlatformFees(asset.clone());
+    let total: i128 = env.storage().persistent().get(&key).unwrap_or(0);
+    let total = total.checked_add(fee).ok_or(Error::Overflow)?;
+    env.storage().persistent().set(&key, &total);
+    Ok(())
+}
+
+/// Remember that a merchant was paid in an asset (at most MAX_ACCEPTED_ASSETS are kept)
+fn record_merchant_asset(env: &Env, merchant: &Address, asset: &Address) {
+    let key = DataKey::MerchantAssets(merchant.clone());
+    let mut assets: Vec<Address> = env.storage().persistent().get(&key).unwrap_or(Vec::new(env));
+    if !assets.contains(asset) && assets.len() < MAX_ACCEPTED_ASSETS {
+        assets.push_back(asset.clone());
+        env.storage().persistent().set(&key, &assets);
+    }
+}
+
+/// Add a completed payment to the contract-wide totals
+fn record_totals(env: &Env, amount: i128, fee: i128) -> Result<(), Error> {
+    let stats = contract_stats(env);
+    let storage = env.storage().instance();
+    bump_counter(env, Counter::Completed);
+    storage.set(&DataKey::Volume, &stats.volume.checked_add(amount).ok_or(Error::Overflow)?);
+    storage.set(&DataKey::FeeTotal, &stats.fees.checked_add(fee).ok_or(Error::Overflow)?);
+    Ok(())
+}
+
+/// Current contract-wide counters
+fn contract_stats(env: &Env) -> ContractStats {
+    let storage = env.storage().instance();
+    ContractStats {
+        sessions: session_count(env),
+        active: active_count(env),
+        completed: counter(env, Counter::Completed),
+        volume: storage.get(&DataKey::Volume).unwrap_or(0),
+        fees: storage.get(&DataKey::FeeTotal).unwrap_or(0),
+    }
+}
+
+/// Add a completed payment to the current hourly stats bucket
+fn record_bucket_stats(env: &Env, amount: i128) -> Result<(), Error> {
+    let key = DataKey::Bucket(env.ledger().timestamp() / STATS_BUCKET_SECONDS);
+    let mut stats: WindowStats = env.storage().persistent().get(&key).unwrap_or_default();
+    stats.count = stats.count.checked_add(1).ok_or(Error::Overflow)?;
+    stats.amount = stats.amount.checked_add(amount).ok_or(Error::Overflow)?;
+    env.storage().persistent().set(&key, &stats);
+    Ok(())
+}
+
+/// Smallest single payment a session accepts, after its bps tolerance
+fn min_acceptable(session: &PaymentSession) -> Result<i128, Error> {
+    let tolerance = session
+        .amount
+        .checked_mul(session.tolerance_bps as i128)
+        .ok_or(Error::Overflow)?
+        / MAX_BPS as i128;
+    session.amount.checked_sub(tolerance).ok_or(Error::Overflow)
+}
+
+/// Total partial payments needed to complete a session
+fn partial_target(session: &PaymentSession) -> Result<i128, Error> {
+    session
+        .amount
+        .checked_mul(session.completion_threshold_bps as i128)
+        .map(|scaled| scaled / MAX_BPS as i128)
+        .ok_or(Error::Overflow)
+}
+
+/// Reject payments above what the session's amount mode accepts
+fn check_overpayment(session: &PaymentSession, amount: i128) -> Result<(), Error> {
+    let too_much = match session.amount_mode {
+        AmountMode::Exact => amount != session.amount,
+        AmountMode::Minimum => {
+            session.max_overpayment > 0
+                && amount > session.amount.saturating_add(session.max_overpayment)
+        }
+    };
+    if too_much {
+        return Err(Error::AmountMismatch);
+    }
+    Ok(())
+}
+
+/// Hash binding a session's completion fields together
+fn attestation_hash(env: &Env, session: &PaymentSession) -> BytesN<32> {
+    let payload = (
+        session.memo.clone(),
+        session.merchant.clone(),
+        session.amount,
+        session.payer.clone(),
+        session.created_at,
+        session.actual_amount,
+        session.completed_at,
+    )
+        .to_xdr(env);
+    env.crypto().sha256(&payload).to_bytes()
+}
+
+/// Whether the session's lifetime has run out at the current ledger time
+fn is_past_expiry(env: &Env, session: &PaymentSession) -> bool {
+    match session.expires_at {
+        Some(expires_at) => env.ledger().timestamp() >= expires_at,
+        None => false,
+    }
+}
+
+/// Move a held escrow session to its final state
+fn settle_escrow(env: &Env, memo: String, status: SessionStatus, topic: Symbol) -> Result<(), Error> {
+    let mut session = load_session(env, &memo)?;
+    if session.status != SessionStatus::Held {
+        return Err(Error::InvalidStatus);
+    }
+    
+    if status == SessionStatus::Refunded {
+        refund_in_full(env, &mut session)?;
+    } else {
+        session.set_status(status);
+    }
+    save_session(env, &memo, &mut session);
+    
+    publish_event(env, topic, (memo, session.merchant));
+    Ok(())
+}
+
+/// Mark a paid session Refunded, taking what is still unrefunded off the merchant total
+fn refund_in_full(env: &Env, session: &mut PaymentSession) -> Result<(), Error> {
+    let paid = session.actual_amount.unwrap_or(session.amount);
+    let unrefunded = paid.checked_sub(session.refunded_amount).ok_or(Error::Overflow)?;
+    add_merchant_total(env, &session.merchant, -unrefunded)?;
+    session.refunded_amount = paid;
+    session.set_status(SessionStatus::Refunded);
+    Ok(())
+}
+
+/// Refund a held escrow session whose hold lapsed without a release, returning whether it did
+/// Only the hold window counts: the checkout expiry stopped mattering once the payment arrived
+fn refund_lapsed_escrow(env: &Env, memo: &String, session: &mut PaymentSession) -> Result<bool, Error> {
+    let lapsed = session
+        .hold_expires_at
+        .is_some_and(|hold_expires_at| env.ledger().timestamp() >= hold_expires_at);
+    if session.status != SessionStatus::Held || !lapsed {
+        return Ok(false);
+    }
+    refund_in_full(env, session)?;
+    save_session(env, memo, session);
+    
+    publish_event(env, symbol_short!("refund"), (memo.clone(), session.merchant.clone()));
+    Ok(true)
+}
+
+/// Load an authorized session together with its authorization
+fn load_authorized(env: &Env, memo: &String) -> Result<(PaymentSession, Authorization), Error> {
+    let session = load_session(env, memo)?;
+    if session.status != SessionStatus::Authorized {
+        return Err(Error::InvalidStatus);
+    }
+    let authorization = env
+        .storage()
+        .persistent()
+        .get(&DataKey::Authorization(memo.clone()))
+        .ok_or(Error::InvalidStatus)?;
+    Ok((session, authorization))
+}
+
+/// Cancel an authorized session (it already left the active indexes at authorize)
+fn void_authorized(env: &Env, memo: String, mut session: PaymentSession) {
+    session.set_status(SessionStatus::Cancelled);
+    save_session(env, &memo, &mut session);
+    bump_counter(env, Counter::Cancelled);
+    publish_event(env, symbol_short!("void"), memo);
+}
+
+/// Refuse refunds recorded more than the configured window after completion
+fn check_refund_window(env: &Env, session: &PaymentSession) -> Result<(), Error> {
+    let window: u64 = setting(env, Setting::RefundWindow).unwrap_or(0);
+    let completed_at = session.completed_at.unwrap_or(0);
+    if window > 0 && env.ledger().timestamp() > completed_at.saturating_add(window) {
+        return Err(Error::RefundWindowClosed);
+    }
+    Ok(())
+}
+
+/// Refuse backend cancellation of a session younger than the deactivation lock
+fn check_deactivation_lock(env: &Env, session: &PaymentSession) -> Result<(), Error> {
+    let lock: u64 = setting(env, Setting::DeactivationLock).unwrap_or(0);
+    if env.ledger().timestamp() < session.created_at.saturating_add(lock) {
+        return Err(Error::DeactivationLocked);
+    }
+    Ok(())
+}
+
+/// Mark an active session inactive and emit the deactivation event
+fn deactivate(env: &Env, memo: String, mut session: PaymentSession) -> Result<(), Error> {
+    session.set_status(SessionStatus::Cancelled);
+    save_session(env, &memo, &mut session);
+    track_closed(env, &session)?;
+    count_ended(env, &session);
+    
+    publish_event(env, symbol_short!("deact"), memo);
+    Ok(())
+}
+
+/// Count a session that left Active without completing, as expired if it was past expiry
+fn count_ended(env: &Env, session: &PaymentSession) {
+    if is_past_expiry(env, session) {
+        bump_counter(env, Counter::Expired);
+    } else {
+        bump_counter(env, Counter::Cancelled);
+    }
+}
+
+/// Current value of a lifecycle counter
+fn counter(env: &Env, counter: Counter) -> u64 {
+    env.storage().instance().get(&DataKey::Counter(counter)).unwrap_or(0)
+}
+
+/// Increment a lifecycle counter, returning its previous value
+fn bump_counter(env: &Env, counter: Counter) -> u64 {
+    let key = DataKey::Counter(counter);
+    let count: u64 = env.storage().instance().get(&key).unwrap_or(0);
+    env.storage().instance().set(&key, &(count + 1));
+    count
+}
+
+/// A configured limit, if set
+fn setting<V: TryFromVal<Env, Val>>(env: &Env, setting: Setting) -> Option<V> {
+    env.storage().instance().get(&DataKey::Setting(setting))
+}
+
+/// Store a configured limit
+fn set_setting<V: IntoVal<Env, Val>>(env: &Env, setting: Setting, value: &V) {
+    env.storage().instance().set(&DataKey::Setting(setting), value);
+}
+
+/// Decrement a lifecycle counter (saturating at zero)
+fn drop_counter(env: &Env, counter: Counter) {
+    let key = DataKey::Counter(counter);
+    let count: u64 = env.storage().instance().get(&key).unwrap_or(0);
+    env.storage().instance().set(&key, &count.saturating_sub(1));
+}
+
+/// Publish an event whose payload is prefixed with the next event sequence number
+/// so indexers can detect gaps
+fn publish_event<T: IntoVal<Env, Val>>(env: &Env, topic: Symbol, data: T) {
+    let key = DataKey::EventSeq;
+    let seq: u64 = env.storage().instance().get(&key).unwrap_or(0) + 1;
+    env.storage().instance().set(&key, &seq);
+    let data: Val = data.into_val(env);
+    env.events().publish((topic,), (seq, data));
+}
+
+/// Total number of sessions ever registered
+fn session_count(env: &Env) -> u64 {
+    env.storage()
+        .instance()
+        .get(&DataKey::SessionCount)
+        .unwrap_or(0)
+}
+
+/// Record a new memo at the next index position
+fn append_session_index(env: &Env, memo: &String) {
+    let index = session_count(env);
+    env.storage().persistent().set(&DataKey::SessionIndex(index), memo);
+    env.storage().instance().set(&DataKey::SessionCount, &(index + 1));
+}
+
+/// Update indexes and counters for a newly active session
+fn track_opened(env: &Env, session: &PaymentSession) -> Result<(), Error> {
+    if let Some(asset) = &session.asset {
+        paged_push(env, &Index::Asset(asset.clone()), &session.memo);
+    }
+    env.storage().instance().set(&DataKey::ActiveCount, &(active_count(env) + 1));
+    let outstanding = total_outstanding(env).checked_add(session.amount).ok_or(Error::Overflow)?;
+    env.storage().instance().set(&DataKey::Outstanding, &outstanding);
+    paged_push(env, &Index::Merchant(session.merchant.clone()), &session.memo);
+    
+    let count = merchant_active_count(env, &session.merchant);
+    set_merchant_active_count(env, &session.merchant, count + 1);
+    if count == 0 {
+        paged_push(env, &Index::ActiveMerchants, &session.merchant);
+    }
+    Ok(())
+}
+
+/// Update indexes and counters for a session that left the Active state
+fn track_closed(env: &Env, session: &PaymentSession) -> Result<(), Error> {
+    if let Some(asset) = &session.asset {
+        paged_remove(env, &Index::Asset(asset.clone()), &session.memo);
+    }
+    env.storage()
+        .instance()
+        .set(&DataKey::ActiveCount, &active_count(env).saturating_sub(1));
+    let outstanding = total_outstanding(env).checked_sub(session.amount).ok_or(Error::Overflow)?;
+    env.storage().instance().set(&DataKey::Outstanding, &outstanding);
+    paged_remove(env, &Index::Merchant(session.merchant.clone()), &session.memo);
+    
+    let count = merchant_active_count(env, &session.merchant).saturating_sub(1);
+    set_merchant_active_count(env, &session.merchant, count);
+    if count == 0 {
+        paged_remove(env, &Index::ActiveMerchants, &session.merchant);
+    }
+    Ok(())
+}
+
+/// Sum of the amounts of all active sessions
+fn total_outstanding(env: &Env) -> i128 {
+    env.storage()
+        .instance()
+        .get(&DataKey::Outstanding)
+        .unwrap_or(0)
+}
+
+/// Number of sessions currently active across all merchants
+fn active_count(env: &Env) -> u64 {
+    env.storage()
+        .instance()
+        .get(&DataKey::ActiveCount)
+        .unwrap_or(0)
+}
+
+/// Memos stored under an index key
+fn index_memos(env: &Env, key: &DataKey) -> Vec<String> {
+    env.storage().persistent().get(key).unwrap_or(Vec::new(env))
+}
+
+/// Append a memo to an index
+fn index_push(env: &Env, key: &DataKey, memo: &String) {
+    let mut memos = index_memos(env, key);
+    memos.push_back(memo.clone());
+    env.storage().persistent().set(key, &memos);
+}
+
+/// Number of memos in a paged index
+fn paged_len(env: &Env, index: &Index) -> u32 {
+    let key = DataKey::Index(IndexEntry::Len(index.clone()));
+    env.storage().persistent().get(&key).unwrap_or(0)
+}
+
+/// Stored page of a paged index (empty past the end)
+fn index_page_at<T>(env: &Env, index: &Index, page: u32) -> Vec<T>
+where
+    T: IntoVal<Env, Val> + TryFromVal<Env, Val>,
+{
+    let key = DataKey::Index(IndexEntry::Page(index.clone(), page));
+    env.storage().persistent().get(&key).unwrap_or(Vec::new(env))
+}
+
+/// Store a page of a paged index, dropping it once empty
+fn set_index_page<T>(env: &Env, index: &Index, page: u32, items: &Vec<T>)
+where
+    T: IntoVal<Env, Val> + TryFromVal<Env, Val>,
+{
+    let key = DataKey::Index(IndexEntry::Page(index.clone(), page));
+    if items.is_empty() {
+        env.storage().persistent().remove(&key);
+    } else {
+        env.storage().persistent().set(&key, items);
+    }
+}
+
+/// Up to `limit` items (capped at MAX_PAGE_SIZE) of a paged index from position `start`
+fn paged_range<T>(env: &Env, index: &Index, start: u32, limit: u32) -> Vec<T>
+where
+    T: IntoVal<Env, Val> + TryFromVal<Env, Val>,
+{
+    let end = start.saturating_add(limit.min(MAX_PAGE_SIZE)).min(paged_len(env, index));
+    let mut items = Vec::new(env);
+    let mut position = start;
+    while position < end {
+        let page = position / INDEX_PAGE_SIZE;
+        let page_start = page * INDEX_PAGE_SIZE;
+        let page_end = end.min(page_start + INDEX_PAGE_SIZE);
+        let stored: Vec<T> = index_page_at(env, index, page);
+        items.append(&stored.slice(position - page_start..page_end - page_start));
+        position = page_end;
+    }
+    items
+}
+
+/// Append an item to a paged index, writing only its last page
+fn paged_push<T: IndexItem>(env: &Env, index: &Index, item: &T) {
+    let position = paged_len(env, index);
+    let mut page = index_page_at(env, index, position / INDEX_PAGE_SIZE);
+    page.push_back(item.clone());
+    set_index_page(env, index, position / INDEX_PAGE_SIZE, &page);
+    
+    let storage = env.storage().persistent();
+    storage.set(&DataKey::Index(item.slot(index)), &position);
+    storage.set(&DataKey::Index(IndexEntry::Len(index.clone())), &(position + 1));
+}
+
+/// Remove an item from a paged index by moving the last item into its position
+fn paged_remove<T: IndexItem>(env: &Env, index: &Index, item: &T) {
+    let storage = env.storage().persistent();
+    let slot_key = DataKey::Index(item.slot(index));
+    let Some(position) = storage.get::<_, u32>(&slot_key) else {
+        return;
+    };
+    let last = paged_len(env, index).saturating_sub(1);
+    let mut last_page: Vec<T> = index_page_at(env, index, last / INDEX_PAGE_SIZE);
+    let Some(moved) = last_page.pop_back() else {
+        return;
+    };
+    storage.remove(&slot_key);
+    set_index_page(env, index, last / INDEX_PAGE_SIZE, &last_page);
+    if position != last {
+        let mut page: Vec<T> = index_page_at(env, index, position / INDEX_PAGE_SIZE);
+        page.set(position % INDEX_PAGE_SIZE, moved.clone());
+        set_index_page(env, index, position / INDEX_PAGE_SIZE, &page);
+        storage.set(&DataKey::Index(moved.slot(index)), &position);
+    }
+    
+    let len_key = DataKey::Index(IndexEntry::Len(index.clone()));
+    if last == 0 {
+        storage.remove(&len_key);
+    } else {
+        storage.set(&len_key, &last);
+    }
+}
+
+/// Number of active sessions a merchant currently has
+fn merchant_active_count(env: &Env, merchant: &Address) -> u32 {
+    env.storage()
+        .persistent()
+        .get(&DataKey::MerchantActive(merchant.clone()))
+        .unwrap_or(0)
+}
+
+fn set_merchant_active_count(env: &Env, merchant: &Address, count: u32) {
+    let key = DataKey::MerchantActive(merchant.clone());
+    if count == 0 {
+        env.storage().persistent().remove(&key);
+    } else {
+        env.storage().persistent().set(&key, &count);
+    }
+}
+
+/// Platform fee on a payment at the session's applicable rate
+fn compute_fee(env: &Env, session: &PaymentSession, amount: i128) -> Result<i128, Error> {
+    fee_for(env, &session.merchant, session.fee_bps, amount)
+}
+
+/// Platform fee a merchant pays on an amount (exempt merchants pay nothing)
+fn fee_for(
+    env: &Env,
+    merchant: &Address,
+    session_bps: Option<u32>,
+    amount: i128,
+) -> Result<i128, Error> {
+    if env.storage().persistent().has(&DataKey::FeeExempt(merchant.clone())) {
+        return Ok(0);
+    }
+    let bps = applicable_fee_bps(env, session_bps, amount);
+    amount
+        .checked_mul(bps as i128)
+        .map(|scaled| scaled / MAX_BPS as i128)
+        .ok_or(Error::Overflow)
+}
+
+/// Fee rate for an amount: a per-session rate, else the highest fee tier the amount meets,
+/// else the global rate
+fn applicable_fee_bps(env: &Env, session_bps: Option<u32>, amount: i128) -> u32 {
+    if let Some(bps) = session_bps {
+        return bps;
+    }
+    
+    let tiers: Vec<FeeTier> = env
+        .storage()
+        .instance()
+        .get(&DataKey::FeeTiers)
+        .unwrap_or(Vec::new(env));
+    let mut tier_bps = None;
+    for tier in tiers.iter() {
+        if amount >= tier.min_amount {
+            tier_bps = Some(tier.bps);
+        }
+    }
+    
+    tier_bps.unwrap_or_else(|| {
+        env.storage()
+            .instance()
+            .get(&DataKey::FeeBps)
+            .unwrap_or(0)
+    })
+}
+
+/// Add the configured creation fee to a merchant's accrued total
+fn accrue_creation_fee(env: &Env, merchant: &Address) -> Result<(), Error> {
+    let fee: i128 = setting(env, Setting::CreationFee).unwrap_or(0);
+    if fee == 0 {
+        return Ok(());
+    }
+    
+    let key = DataKey::Accrued(merchant.clone());
+    let accrued: i128 = env.storage().persistent().get(&key).unwrap_or(0);
+    let accrued = accrued.checked_add(fee).ok_or(Error::Overflow)?;
+    env.storage().persistent().set(&key, &accrued);
+    Ok(())
+}
+
+/// Canonical memo form: trimmed, lowercased, limited to `[a-z0-9_-]`
+fn normalize_memo(env: &Env, memo: &String) -> Result<String, Error> {
+    let len = memo.len() as usize;
+    if len > MAX_RAW_MEMO_LEN {
+        return Err(Error::InvalidMemo);
+    }
+    let mut raw = [0u8; MAX_RAW_MEMO_LEN];
+    memo.copy_into_slice(&mut raw[..len]);
+    
+    let trimmed = raw[..len].trim_ascii();
+    if trimmed.is_empty() || trimmed.len() > MAX_MEMO_LEN {
+        return Err(Error::InvalidMemo);
+    }
+    
+    let mut canonical = [0u8; MAX_MEMO_LEN];
+    for (out, byte) in canonical.iter_mut().zip(trimmed) {
+        let byte = byte.to_ascii_lowercase();
+        if !(byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-') {
+            return Err(Error::InvalidMemo);
+        }
+        *out = byte;
+    }
+    Ok(String::from_bytes(env, &canonical[..trimmed.len()]))
+}
+
+/// Caller-chosen memos can't take a generated memo before the contract does
+fn check_memo_unreserved(memo: &String) -> Result<(), Error> {
+    let len = memo.len() as usize;
+    let mut bytes = [0u8; MAX_MEMO_LEN];
+    memo.copy_into_slice(&mut bytes[..len]);
+    if RESERVED_MEMO_PREFIXES.iter().any(|prefix| bytes[..len].starts_with(prefix)) {
+        return Err(Error::InvalidMemo);
+    }
+    Ok(())
+}
+
+/// Merchants on shared infrastructure only accept memos under their prefix
+fn check_memo_prefix(env: &Env, memo: &String, merchant: &Address) -> Result<(), Error> {
+    let prefix: Option<String> =
+        env.storage().persistent().get(&DataKey::MemoPrefix(merchant.clone()));
+    if prefix.is_some_and(|prefix| !has_prefix(memo, &prefix)) {
+        return Err(Error::InvalidMemo);
+    }
+    Ok(())
+}
+
+/// Whether a canonical memo starts with a canonical prefix
+fn has_prefix(memo: &String, prefix: &String) -> bool {
+    let (len, prefix_len) = (memo.len() as usize, prefix.len() as usize);
+    if prefix_len > len {
+        return false;
+    }
+    let mut memo_bytes = [0u8; MAX_MEMO_LEN];
+    let mut prefix_bytes = [0u8; MAX_MEMO_LEN];
+    memo.copy_into_slice(&mut memo_bytes[..len]);
+    prefix.copy_into_slice(&mut prefix_bytes[..prefix_len]);
+    memo_bytes[..len].starts_with(&prefix_bytes[..prefix_len])
+}
+
+/// Invoke the session's completion callback, recording the attempt and whether it succeeded
+/// A failing callback doesn't fail the payment (it's logged as cb_fail); retry_callback can re-invoke it
+fn notify_callback(env: &Env, session: &mut PaymentSession) {
+    if let Some(callback) = &session.callback {
+        session.callback_attempts = session.callback_attempts.saturating_add(1);
+        let args = Vec::from_array(env, [session.memo.into_val(env)]);
+        let result = env.try_invoke_contract::<(), soroban_sdk::Error>(
+            callback,
+            &Symbol::new(env, "on_payment"),
+            args,
+        );
+        session.callback_delivered = matches!(result, Ok(Ok(())));
+        if !session.callback_delivered {
+            publish_event(
+                env,
+                symbol_short!("cb_fail"),
+                (session.memo.clone(), callback.clone(), session.callback_attempts),
+            );
+        }
+    }
+}
+
+/// Storage key of a session, under the canonical memo so every spelling of it agrees
+fn session_key(env: &Env, memo: &String) -> DataKey {
+    DataKey::Session(normalize_memo(env, memo).unwrap_or(memo.clone()))
+}
+
+/// Store a changed session, stamping its last activity
+fn save_session(env: &Env, memo: &String, session: &mut PaymentSession) {
+    session.last_activity = env.ledger().timestamp();
+    env.storage().persistent().set(&session_key(env, memo), session);
+}
+
+/// Load a stored session by memo
+fn load_session(env: &Env, memo: &String) -> Result<PaymentSession, Error> {
+    env.storage()
+        .persistent()
+        .get(&session_key(env, memo))
+        .ok_or(Error::SessionNotFound)
+}
+
+/// Load the backend address and require its authorization
+fn require_backend(env: &Env) -> Result<Address, Error> {
+    let backend: Address = env
+        .storage()
+        .instance()
+        .get(&DataKey::Backend)
+        .ok_or(Error::Unauthorized)?;
+    backend.require_auth();
+    check_schema(env)?;
+    check_deadman(env)?;
+    env.storage().instance().set(&DataKey::LastBackendCall, &env.ledger().timestamp());
+    Ok(backend)
+}
+
+/// Refuse work once the backend has been silent longer than the dead-man window
+/// A failed call doesn't record activity, so the pause holds until reset_deadman
+fn check_deadman(env: &Env) -> Result<(), Error> {
+    let window: u64 = setting(env, Setting::Deadman).unwrap_or(0);
+    let last_call: Option<u64> = env.storage().instance().get(&DataKey::LastBackendCall);
+    if let (true, Some(last_call)) = (window > 0, last_call) {
+        if env.ledger().timestamp() > last_call.saturating_add(window) {
+            return Err(Error::ContractPaused);
+        }
+    }
+    Ok(())
+}
+
+/// Load the admin address and require its authorization
+fn require_admin(env: &Env) -> Result<Address, Error> {
+    let admin: Address = env
+        .storage()
+        .instance()
+        .get(&DataKey::Admin)
+        .ok_or(Error::Unauthorized)?;
+    admin.require_auth();
+    check_schema(env)?;
+    Ok(admin)
+}
+
+/// Admin auth for configuration changes, which a sealed contract refuses
+fn require_config_admin(env: &Env) -> Result<Address, Error> {
+    let admin = require_admin(env)?;
+    if env.storage().instance().has(&DataKey::Sealed) {
+        return Err(Error::ContractSealed);
+    }
+    Ok(admin)
+}
+
+/// Refuse to write over data stored in an older layout than this code expects
+fn check_schema(env: &Env) -> Result<(), Error> {
+    let stored: u32 = env.storage().instance().get(&DataKey::SchemaVersion).unwrap_or(0);
+    if stored < SCHEMA_VERSION {
+        return Err(Error::SchemaMismatch);
+    }
+    Ok(())
+}
+
+/// Topic completion events are published under
+fn completion_topic(env: &Env) -> Symbol {
+    env.storage()
+        .instance()
+        .get(&DataKey::CompletionTopic)
+        .unwrap_or(symbol_short!("valid"))
+}
+
+/// Topic registration events are published under
+fn registration_topic(env: &Env) -> Symbol {
+    env.storage()
+        .instance()
+        .get(&DataKey::RegistrationTopic)
+        .unwrap_or(symbol_short!("reg_sess"))
+}
+
+/// Accepted-asset allowlist (empty = any asset accepted)
+fn accepted_assets(env: &Env) -> Vec<Address> {
+    env.storage()
+        .instance()
+        .get(&DataKey::Assets)
+        .unwrap_or(Vec::new(env))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use soroban_sdk::testutils::storage::Persistent as _;
+    use soroban_sdk::testutils::{Address as _, Events, Ledger, MockAuth, MockAuthInvoke};
+    use soroban_sdk::{Address, Env, IntoVal};
+
+    /// Completion callback that fails until marked ready
+    #[contract]
+    struct CallbackReceiver;
+    
+    #[contractimpl]
+    impl CallbackReceiver {
+        pub fn on_payment(env: Env, memo: String) {
+            if !env.storage().instance().has(&symbol_short!("READY")) {
+                panic!("receiver not ready");
+            }
+            env.storage().instance().set(&symbol_short!("LAST"), &memo);
+        }
+    }
+    
+    /// Deploy and initialize a validator with generated backend and admin
+    fn setup(env: &Env) -> (ChainPeValidatorClient<'_>, Address, Address) {
+        setup_with(env, InitConfig::default())
+    }
+
+    /// Deploy and initialize a validator with the given configuration
+    fn setup_with(env: &Env, config: InitConfig) -> (ChainPeValidatorClient<'_>, Address, Address) {
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, ChainPeValidator);
+        let client = ChainPeValidatorClient::new(env, &contract_id);
+        
+        let backend = Address::generate(env);
+        let admin = Address::generate(env);
+        client.initialize(&backend, &Some(admin.clone()), &config);
+        
+        (client, backend, admin)
+    }
+
+    #[test]
     fn test_payment_flow() {
         let env = Env::default();
-        let contract_id = env.register_contract(None, ChainPeValidator);
-        let client = ChainPeValidatorClient::new(&env, &contract_id);
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, ChainPeValidator);
+        let client = ChainPeValidatorClient::new(&env, &contract_id);
+        
+        let backend = Address::generate(&env);
+        let merchant = Address::generate(&env);
+        
+        // Initialize
+        client.initialize(&backend, &None, &InitConfig::default());
+        
+        // Register session
+        let memo = String::from_str(&env, "pay_test123");
+        client.register_session(&memo, &merchant, &100);
+        
+        // Verify session
+        let session = client.get_session(&memo);
+        assert!(session.is_some());
+        assert!(session.unwrap().is_active);
+    }
+
+    #[test]
+    fn test_asset_allowlist() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let usdc = Address::generate(&env);
+        let fake = Address::generate(&env);
+        
+        // Empty allowlist accepts any asset
+        let options = SessionOptions { asset: Some(fake.clone()), ..Default::default() };
+        client.register_session_with(&String::from_str(&env, "pay_any"), &merchant, &100, &options);
+        
+        client.add_asset(&usdc);
+        
+        // Listed asset is accepted
+        let options = SessionOptions { asset: Some(usdc.clone()), ..Default::default() };
+        let memo = String::from_str(&env, "pay_usdc");
+        client.register_session_with(&memo, &merchant, &100, &options);
+        assert_eq!(client.get_session(&memo).unwrap().asset, Some(usdc.clone()));
+        
+        // Unlisted asset is rejected
+        let options = SessionOptions { asset: Some(fake.clone()), ..Default::default() };
+        let result = client.try_register_session_with(
+            &String::from_str(&env, "pay_fake"), &merchant, &100, &options,
+        );
+        assert_eq!(result, Err(Ok(Error::AssetNotAllowed)));
+        
+        // Removing the last asset reopens the allowlist
+        client.remove_asset(&usdc);
+        client.register_session_with(&String::from_str(&env, "pay_fake"), &merchant, &100, &options);
+    }
+
+    #[test]
+    fn test_validate_payment_full_records_payer() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let payer = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_payer");
+        client.register_session(&memo, &merchant, &100);
+        assert_eq!(client.get_session(&memo).unwrap().payer, None);
+        
+        assert!(client.validate_payment_full(&memo, &100, &payer));
+        
+        // Completion and payer land in the same stored session
+        let session = client.get_session(&memo).unwrap();
+        assert!(!session.is_active);
+        assert_eq!(session.payer, Some(payer.clone()));
+        
+        // Completion event carries the payer
+        let (_, _, data) = env.events().all().last().unwrap();
+        let (_, event): (u64, CompletionEvent) = data.into_val(&env);
+        assert_eq!(event.payer, Some(payer));
+        assert_eq!(event.merchant, merchant);
+    }
+
+    #[test]
+    fn test_custom_completion_topic() {
+        let env = Env::default();
+        let merchant = Address::generate(&env);
+        let memo = String::from_str(&env, "pay_topic");
+        
+        // Default topic
+        let (client, _, _) = setup(&env);
+        client.register_session(&memo, &merchant, &100);
+        client.validate_payment(&memo, &100);
+        let (_, topics, _) = env.events().all().last().unwrap();
+        assert_eq!(topics, (symbol_short!("valid"),).into_val(&env));
+        
+        // Configured topic
+        let config = InitConfig { completion_topic: Some(symbol_short!("pay_done")), ..Default::default() };
+        let (client, _, _) = setup_with(&env, config);
+        client.register_session(&memo, &merchant, &100);
+        client.validate_payment(&memo, &100);
+        let (_, topics, _) = env.events().all().last().unwrap();
+        assert_eq!(topics, (symbol_short!("pay_done"),).into_val(&env));
+    }
+
+    #[test]
+    fn test_verify_session() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let other = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_verify");
+        client.register_session(&memo, &merchant, &100);
+        
+        // Matching merchant and amount
+        assert!(client.verify_session(&memo, &merchant, &100));
+        assert!(client.verify_session(&memo, &merchant, &50));
+        
+        // Merchant and amount mismatches
+        assert!(!client.verify_session(&memo, &other, &100));
+        assert!(!client.verify_session(&memo, &merchant, &101));
+        
+        // Typed errors for missing and inactive sessions
+        let missing = String::from_str(&env, "pay_missing");
+        assert_eq!(client.try_verify_session(&missing, &merchant, &100), Err(Ok(Error::SessionNotFound)));
+        client.deactivate_session(&memo);
+        assert_eq!(client.try_verify_session(&memo, &merchant, &100), Err(Ok(Error::SessionExpired)));
+        
+        // A session past its expiry is reported expired before anyone is shown a pay button
+        let timed = String::from_str(&env, "pay_verify_ttl");
+        let options = SessionOptions { ttl_seconds: 60, ..Default::default() };
+        client.register_session_with(&timed, &merchant, &100, &options);
+        assert!(client.verify_session(&timed, &merchant, &100));
+        env.ledger().with_mut(|li| li.timestamp += 120);
+        assert_eq!(client.try_verify_session(&timed, &merchant, &100), Err(Ok(Error::SessionExpired)));
+    }
+
+    #[test]
+    fn test_deactivate_already_inactive() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_deact");
+        client.register_session(&memo, &merchant, &100);
+        
+        // First deactivation succeeds, second reports the session is already inactive
+        client.deactivate_session(&memo);
+        assert!(!client.get_session(&memo).unwrap().is_active);
+        assert_eq!(client.try_deactivate_session(&memo), Err(Ok(Error::AlreadyInactive)));
+        
+        // force_deactivate is idempotent
+        client.force_deactivate(&memo);
+        client.force_deactivate(&memo);
+        assert!(!client.get_session(&memo).unwrap().is_active);
+        
+        let fresh = String::from_str(&env, "pay_force");
+        client.register_session(&fresh, &merchant, &100);
+        client.force_deactivate(&fresh);
+        assert!(!client.get_session(&fresh).unwrap().is_active);
+    }
+
+    #[test]
+    fn test_global_fee() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        assert_eq!(client.try_set_global_fee_bps(&10_001), Err(Ok(Error::InvalidBps)));
+        client.set_global_fee_bps(&250);
+        
+        // Session without its own fee pays the global rate
+        let global = String::from_str(&env, "pay_global");
+        client.register_session(&global, &merchant, &10_000);
+        client.validate_payment(&global, &10_000);
+        assert_eq!(client.get_session(&global).unwrap().fee, 250);
+        
+        // Per-session fee overrides the global rate
+        let own = String::from_str(&env, "pay_own");
+        let options = SessionOptions { fee_bps: Some(100), ..Default::default() };
+        client.register_session_with(&own, &merchant, &10_000, &options);
+        client.validate_payment(&own, &10_000);
+        assert_eq!(client.get_session(&own).unwrap().fee, 100);
+        
+        let options = SessionOptions { fee_bps: Some(10_001), ..Default::default() };
+        let bad = String::from_str(&env, "pay_bad_fee");
+        assert_eq!(
+            client.try_register_session_with(&bad, &merchant, &10_000, &options),
+            Err(Ok(Error::InvalidBps))
+        );
+    }
+
+    #[test]
+    fn test_memo_normalization() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        // Stored under the canonical form
+        client.register_session(&String::from_str(&env, "  Pay_ABC123 "), &merchant, &100);
+        let canonical = String::from_str(&env, "pay_abc123");
+        assert_eq!(client.get_session(&canonical).unwrap().memo, canonical);
+        
+        // Memos differing only by case/whitespace collide
+        assert_eq!(
+            client.try_register_session(&String::from_str(&env, "PAY_abc123"), &merchant, &100),
+            Err(Ok(Error::SessionAlreadyExists))
+        );
+        
+        // Empty and out-of-charset memos are rejected
+        for memo in ["   ", "", "pay abc", "pay!"] {
+            assert_eq!(
+                client.try_register_session(&String::from_str(&env, memo), &merchant, &100),
+                Err(Ok(Error::InvalidMemo))
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_payer() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let payer = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_get_payer");
+        client.register_session(&memo, &merchant, &100);
+        assert_eq!(client.get_payer(&memo), None);
+        
+        client.validate_payment_full(&memo, &100, &payer);
+        assert_eq!(client.get_payer(&memo), Some(payer));
+        
+        // Completed without a payer, or never registered
+        let unbound = String::from_str(&env, "pay_unbound");
+        client.register_session(&unbound, &merchant, &100);
+        client.validate_payment(&unbound, &100);
+        assert_eq!(client.get_payer(&unbound), None);
+        assert_eq!(client.get_payer(&String::from_str(&env, "pay_missing")), None);
+    }
+
+    #[test]
+    fn test_escrow_hold_release_and_refund() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let options = SessionOptions { escrow: true, ..Default::default() };
+        
+        // Hold then release
+        let released = String::from_str(&env, "pay_release");
+        client.register_session_with(&released, &merchant, &100, &options);
+        client.validate_payment(&released, &100);
+        let session = client.get_session(&released).unwrap();
+        assert_eq!(session.status, SessionStatus::Held);
+        assert!(!session.is_active);
+        client.release_escrow(&released);
+        assert_eq!(client.get_session(&released).unwrap().status, SessionStatus::Completed);
+        assert_eq!(client.try_release_escrow(&released), Err(Ok(Error::InvalidStatus)));
+        
+        // Hold then refund
+        let refunded = String::from_str(&env, "pay_refund");
+        client.register_session_with(&refunded, &merchant, &100, &options);
+        client.validate_payment(&refunded, &100);
+        client.refund_escrow(&refunded);
+        assert_eq!(client.get_session(&refunded).unwrap().status, SessionStatus::Refunded);
+        
+        // Non-escrow sessions complete immediately and can't be released
+        let direct = String::from_str(&env, "pay_direct");
+        client.register_session(&direct, &merchant, &100);
+        client.validate_payment(&direct, &100);
+        assert_eq!(client.get_session(&direct).unwrap().status, SessionStatus::Completed);
+        assert_eq!(client.try_refund_escrow(&direct), Err(Ok(Error::InvalidStatus)));
+    }
+
+    #[test]
+    fn test_max_ttl() {
+        let env = Env::default();
+        let config = InitConfig { max_ttl_seconds: 3600, ..Default::default() };
+        let (client, _, _) = setup_with(&env, config);
+        let merchant = Address::generate(&env);
+        env.ledger().with_mut(|li| li.timestamp = 1000);
+        
+        // Within the cap
+        let within = String::from_str(&env, "pay_within");
+        let options = SessionOptions { ttl_seconds: 600, ..Default::default() };
+        client.register_session_with(&within, &merchant, &100, &options);
+        assert_eq!(client.get_session(&within).unwrap().expires_at, Some(1600));
+        
+        // Beyond the cap
+        let beyond = String::from_str(&env, "pay_beyond");
+        let options = SessionOptions { ttl_seconds: 3601, ..Default::default() };
+        assert_eq!(
+            client.try_register_session_with(&beyond, &merchant, &100, &options),
+            Err(Ok(Error::TtlTooLong))
+        );
+        
+        // No per-session TTL falls back to the cap
+        let default = String::from_str(&env, "pay_default_ttl");
+        client.register_session(&default, &merchant, &100);
+        assert_eq!(client.get_session(&default).unwrap().expires_at, Some(4600));
+        
+        // Expired sessions reject payment
+        env.ledger().with_mut(|li| li.timestamp = 1600);
+        assert_eq!(client.try_validate_payment(&within, &100), Err(Ok(Error::SessionExpired)));
+    }
+
+    #[test]
+    fn test_is_initialized() {
+        let env = Env::default();
+        let contract_id = env.register_contract(None, ChainPeValidator);
+        let client = ChainPeValidatorClient::new(&env, &contract_id);
+        
+        assert!(!client.is_initialized());
+        client.initialize(&Address::generate(&env), &None, &InitConfig::default());
+        assert!(client.is_initialized());
+    }
+
+    #[test]
+    fn test_creation_fee_accrual() {
+        let env = Env::default();
+        let config = InitConfig { creation_fee: 25, ..Default::default() };
+        let (client, _, _) = setup_with(&env, config);
+        let merchant = Address::generate(&env);
+        let other = Address::generate(&env);
+        
+        client.register_session(&String::from_str(&env, "pay_fee_1"), &merchant, &100);
+        client.register_session(&String::from_str(&env, "pay_fee_2"), &merchant, &100);
+        assert_eq!(client.get_accrued_fees(&merchant), 50);
+        assert_eq!(client.get_accrued_fees(&other), 0);
+        
+        client.reset_accrued_fees(&merchant);
+        assert_eq!(client.get_accrued_fees(&merchant), 0);
+    }
+
+    #[test]
+    fn test_active_merchants() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let first = Address::generate(&env);
+        let second = Address::generate(&env);
+        
+        let only = String::from_str(&env, "pay_first");
+        client.register_session(&only, &first, &100);
+        client.register_session(&String::from_str(&env, "pay_second_1"), &second, &100);
+        client.register_session(&String::from_str(&env, "pay_second_2"), &second, &100);
+        
+        let (merchants, truncated) = client.get_active_merchants();
+        assert_eq!(merchants.len(), 2);
+        assert!(!truncated);
+        
+        // Deactivating the first merchant's only session removes them
+        client.deactivate_session(&only);
+        let (merchants, _) = client.get_active_merchants();
+        assert_eq!(merchants, Vec::from_array(&env, [second.clone()]));
+        
+        // Completing one of two sessions keeps the merchant listed
+        client.validate_payment(&String::from_str(&env, "pay_second_1"), &100);
+        let (merchants, _) = client.get_active_merchants();
+        assert_eq!(merchants, Vec::from_array(&env, [second]));
+    }
+
+    #[test]
+    fn test_active_merchants_paged() {
+        let env = Env::default();
+        env.budget().reset_unlimited();
+        let (client, _, _) = setup(&env);
+        
+        let mut memos = Vec::new(&env);
+        for n in 0..=MAX_ACTIVE_MERCHANTS {
+            let memo = [b'p', b'_', b'0' + (n / 10) as u8, b'0' + (n % 10) as u8];
+            let memo = String::from_bytes(&env, &memo);
+            client.register_session(&memo, &Address::generate(&env), &100);
+            memos.push_back(memo);
+        }
+        
+        // Reads stop at one page of the index
+        let (merchants, truncated) = client.get_active_merchants();
+        assert_eq!(merchants.len(), MAX_ACTIVE_MERCHANTS);
+        assert!(truncated);
+        assert!(client.check_invariants());
+        
+        // A merchant leaving the first page is replaced by the last one listed
+        client.deactivate_session(&memos.get(0).unwrap());
+        let (merchants, truncated) = client.get_active_merchants();
+        assert_eq!(merchants.len(), MAX_ACTIVE_MERCHANTS);
+        assert!(!truncated);
+        assert!(client.check_invariants());
+    }
+
+    #[test]
+    fn test_completion_event_ledger_sequence() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_sequence");
+        client.register_session(&memo, &merchant, &100);
+        let registered_at = env.ledger().sequence();
+        env.ledger().with_mut(|li| li.sequence_number += 5);
+        client.validate_payment(&memo, &100);
+        
+        let (_, _, data) = env.events().all().last().unwrap();
+        let (_, event): (u64, CompletionEvent) = data.into_val(&env);
+        assert_eq!(event.ledger_seq, registered_at + 5);
+    }
+
+    #[test]
+    fn test_future_dated_session_rejected() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        env.ledger().with_mut(|li| li.timestamp = 1000);
+        
+        let memo = String::from_str(&env, "pay_future");
+        client.register_session(&memo, &merchant, &100);
+        
+        // Simulate an inconsistent timestamp by rewriting the stored session
+        env.as_contract(&client.address, || {
+            let key = DataKey::Session(memo.clone());
+            let mut session: PaymentSession = env.storage().persistent().get(&key).unwrap();
+            session.created_at = 5000;
+            env.storage().persistent().set(&key, &session);
+        });
+        assert_eq!(client.try_validate_payment(&memo, &100), Err(Ok(Error::SessionNotStarted)));
+        
+        env.ledger().with_mut(|li| li.timestamp = 5000);
+        assert!(client.validate_payment(&memo, &100));
+    }
+
+    #[test]
+    fn test_get_session_by_index() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memos = ["pay_idx_0", "pay_idx_1", "pay_idx_2"];
+        for memo in memos {
+            client.register_session(&String::from_str(&env, memo), &merchant, &100);
+        }
+        
+        assert_eq!(client.get_session_count(), 3);
+        for (index, memo) in memos.iter().enumerate() {
+            let session = client.get_session_by_index(&(index as u64)).unwrap();
+            assert_eq!(session.memo, String::from_str(&env, memo));
+        }
+        assert!(client.get_session_by_index(&3).is_none());
+    }
+
+    #[test]
+    fn test_max_overpayment() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let options = SessionOptions { max_overpayment: 10, ..Default::default() };
+        
+        // Surplus within the allowance
+        let within = String::from_str(&env, "pay_over_ok");
+        client.register_session_with(&within, &merchant, &100, &options);
+        assert!(client.validate_payment(&within, &110));
+        
+        // Surplus beyond the allowance
+        let beyond = String::from_str(&env, "pay_over_bad");
+        client.register_session_with(&beyond, &merchant, &100, &options);
+        assert_eq!(client.try_validate_payment(&beyond, &111), Err(Ok(Error::AmountMismatch)));
+        
+        // Default keeps unlimited overpayment
+        let unlimited = String::from_str(&env, "pay_over_any");
+        client.register_session(&unlimited, &merchant, &100);
+        assert!(client.validate_payment(&unlimited, &1_000_000));
+        
+        // Exact mode rejects any surplus
+        let exact = String::from_str(&env, "pay_exact");
+        let options = SessionOptions { amount_mode: AmountMode::Exact, ..Default::default() };
+        client.register_session_with(&exact, &merchant, &100, &options);
+        assert_eq!(client.try_validate_payment(&exact, &101), Err(Ok(Error::AmountMismatch)));
+        assert!(client.validate_payment(&exact, &100));
+    }
+
+    #[test]
+    fn test_register_or_get() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let memo = String::from_str(&env, "pay_idem");
+        
+        // First call creates
+        let created = client.register_or_get(&memo, &merchant, &100);
+        assert_eq!(created.amount, 100);
+        assert_eq!(client.get_session_count(), 1);
+        
+        // Retry returns the same session without registering again
+        let retried = client.register_or_get(&memo, &merchant, &100);
+        assert_eq!(retried, created);
+        assert_eq!(client.get_session_count(), 1);
+        
+        // Conflicting merchant or amount is rejected
+        assert_eq!(
+            client.try_register_or_get(&memo, &merchant, &200),
+            Err(Ok(Error::SessionAlreadyExists))
+        );
+        assert_eq!(
+            client.try_register_or_get(&memo, &Address::generate(&env), &100),
+            Err(Ok(Error::SessionAlreadyExists))
+        );
+    }
+
+    #[test]
+    fn test_get_controllers() {
+        let env = Env::default();
+        let (client, backend, admin) = setup(&env);
+        
+        let controllers = client.get_controllers();
+        assert_eq!(controllers.backend, backend);
+        assert_eq!(controllers.admin, Some(admin.clone()));
+        assert_eq!(controllers.pending_admin, None);
+        
+        // Two-step transfer shows up as pending until accepted
+        let next = Address::generate(&env);
+        client.transfer_admin(&next);
+        assert_eq!(client.get_controllers().pending_admin, Some(next.clone()));
+        client.accept_admin();
+        let controllers = client.get_controllers();
+        assert_eq!(controllers.admin, Some(next));
+        assert_eq!(controllers.pending_admin, None);
+    }
+
+    #[test]
+    fn test_validate_normalizes_memo() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        client.register_session(&String::from_str(&env, "pay_canon"), &merchant, &100);
+        assert!(client.validate_payment(&String::from_str(&env, " PAY_Canon "), &100));
+        
+        let session = client.get_session(&String::from_str(&env, "pay_canon")).unwrap();
+        assert_eq!(session.status, SessionStatus::Completed);
+    }
+
+    #[test]
+    fn test_attest_completion() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let payer = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_attest");
+        client.register_session(&memo, &merchant, &100);
+        assert_eq!(client.try_attest_completion(&memo), Err(Ok(Error::NotCompleted)));
+        client.validate_payment_full(&memo, &100, &payer);
+        
+        // Deterministic and matches a recomputation over the session fields
+        let hash = client.attest_completion(&memo);
+        assert_eq!(client.attest_completion(&memo), hash);
+        let session = client.get_session(&memo).unwrap();
+        let payload = (
+            memo.clone(),
+            merchant.clone(),
+            100i128,
+            Some(payer.clone()),
+            session.created_at,
+            Some(100i128),
+            session.completed_at,
+        );
+        assert_eq!(hash, env.crypto().sha256(&payload.to_xdr(&env)).to_bytes());
+        
+        // Any differing field changes the hash
+        let mut altered = session.clone();
+        altered.amount = 101;
+        assert_ne!(attestation_hash(&env, &altered), hash);
+        let mut altered = session.clone();
+        altered.payer = Some(Address::generate(&env));
+        assert_ne!(attestation_hash(&env, &altered), hash);
+        let mut altered = session.clone();
+        altered.created_at += 1;
+        assert_ne!(attestation_hash(&env, &altered), hash);
+        let mut altered = session.clone();
+        altered.actual_amount = Some(140);
+        assert_ne!(attestation_hash(&env, &altered), hash);
+        let mut altered = session;
+        altered.completed_at = altered.completed_at.map(|at| at + 1);
+        assert_ne!(attestation_hash(&env, &altered), hash);
+    }
+
+    #[test]
+    fn test_partial_payments_capped() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_partials");
+        let options = SessionOptions { max_partials: 3, ..Default::default() };
+        client.register_session_with(&memo, &merchant, &100, &options);
+        
+        for _ in 0..3 {
+            client.record_partial_payment(&memo, &1);
+        }
+        let session = client.get_session(&memo).unwrap();
+        assert_eq!(session.paid_so_far, 3);
+        assert_eq!(session.partial_count, 3);
+        assert_eq!(client.try_record_partial_payment(&memo, &1), Err(Ok(Error::TooManyPartials)));
+        
+        // Default cap applies when none is given
+        let default = String::from_str(&env, "pay_partials_default");
+        client.register_session(&default, &merchant, &100);
+        assert_eq!(client.get_session(&default).unwrap().max_partials, DEFAULT_MAX_PARTIALS);
+    }
+
+    #[test]
+    fn test_partial_payments_complete_session() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_split");
+        client.register_session(&memo, &merchant, &100);
+        assert_eq!(client.record_partial_payment(&memo, &60), 40);
+        assert_eq!(client.get_session(&memo).unwrap().status, SessionStatus::Active);
+        assert_eq!(client.record_partial_payment(&memo, &40), 0);
+        
+        let session = client.get_session(&memo).unwrap();
+        assert_eq!(session.status, SessionStatus::Completed);
+        assert_eq!(session.paid_so_far, 100);
+    }
+
+    #[test]
+    fn test_stats_since() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        // One payment in the first hour
+        env.ledger().with_mut(|li| li.timestamp = 3_600);
+        let early = String::from_str(&env, "pay_early");
+        client.register_session(&early, &merchant, &100);
+        client.validate_payment(&early, &100);
+        
+        // Two payments two hours later
+        env.ledger().with_mut(|li| li.timestamp = 3 * 3_600 + 10);
+        for memo in ["pay_late_1", "pay_late_2"] {
+            let memo = String::from_str(&env, memo);
+            client.register_session(&memo, &merchant, &50);
+            client.validate_payment(&memo, &50);
+        }
+        
+        assert_eq!(client.get_stats_since(&(3 * 3_600)), WindowStats { count: 2, amount: 100 });
+        assert_eq!(client.get_stats_since(&0), WindowStats { count: 3, amount: 200 });
+    }
+
+    #[test]
+    fn test_cancel_merchant_sessions() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let other = Address::generate(&env);
+        
+        let memos = ["pay_off_1", "pay_off_2", "pay_off_3"];
+        for memo in memos {
+            client.register_session(&String::from_str(&env, memo), &merchant, &100);
+        }
+        let kept = String::from_str(&env, "pay_kept");
+        client.register_session(&kept, &other, &100);
+        
+        assert_eq!(client.cancel_merchant_sessions(&merchant), 0);
+        for memo in memos {
+            let session = client.get_session(&String::from_str(&env, memo)).unwrap();
+            assert_eq!(session.status, SessionStatus::Cancelled);
+        }
+        assert!(client.get_session(&kept).unwrap().is_active);
+        
+        let (_, topics, data) = env.events().all().last().unwrap();
+        assert_eq!(topics, (Symbol::new(&env, "cancel_all"),).into_val(&env));
+        let (_, (_, count)): (u64, (Address, u32)) = data.into_val(&env);
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn test_cancel_merchant_sessions_batched() {
+        let env = Env::default();
+        env.budget().reset_unlimited();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        for i in 0..MAX_CANCEL_BATCH + 2 {
+            let memo = String::from_bytes(&env, &[b'p', b'_', b'a' + (i / 26) as u8, b'a' + (i % 26) as u8]);
+            client.register_session(&memo, &merchant, &100);
+        }
+        
+        assert_eq!(client.cancel_merchant_sessions(&merchant), 2);
+        assert_eq!(client.cancel_merchant_sessions(&merchant), 0);
+        assert!(client.get_active_merchants().0.is_empty());
+    }
+
+    #[test]
+    fn test_require_customer_auth() {
+        let env = Env::default();
+        let (client, backend, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let payer = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_cosign");
+        let options = SessionOptions { require_customer_auth: true, ..Default::default() };
+        client.register_session_with(&memo, &merchant, &100, &options);
+        
+        // Completion without a payer can't satisfy customer auth
+        assert_eq!(client.try_validate_payment(&memo, &100), Err(Ok(Error::Unauthorized)));
+        
+        let args = (memo.clone(), 100i128, payer.clone()).into_val(&env);
+        let invoke = MockAuthInvoke {
+            contract: &client.address,
+            fn_name: "validate_payment_full",
+            args,
+            sub_invokes: &[],
+        };
+        
+        // Backend alone is not enough
+        env.mock_auths(&[MockAuth { address: &backend, invoke: &invoke }]);
+        assert!(client.try_validate_payment_full(&memo, &100, &payer).is_err());
+        assert!(client.get_session(&memo).unwrap().is_active);
+        
+        // Backend plus payer signatures complete the session
+        env.mock_auths(&[
+            MockAuth { address: &backend, invoke: &invoke },
+            MockAuth { address: &payer, invoke: &invoke },
+        ]);
+        assert!(client.validate_payment_full(&memo, &100, &payer));
+        assert_eq!(client.get_session(&memo).unwrap().status, SessionStatus::Completed);
+    }
+
+    #[test]
+    fn test_get_accepted_assets() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        assert!(client.get_accepted_assets().is_empty());
+        
+        let usdc = Address::generate(&env);
+        let eurc = Address::generate(&env);
+        client.add_asset(&usdc);
+        client.add_asset(&eurc);
+        client.add_asset(&usdc);
+        assert_eq!(client.get_accepted_assets(), Vec::from_array(&env, [usdc, eurc]));
+    }
+
+    #[test]
+    fn test_refund_linkage() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let original = String::from_str(&env, "pay_original");
+        client.register_session(&original, &merchant, &100);
+        client.validate_payment(&original, &100);
+        assert!(client.get_refunds_for(&original).is_empty());
+        
+        let refund = String::from_str(&env, "ref_original");
+        let options = SessionOptions { refund_of: Some(original.clone()), ..Default::default() };
+        client.register_session_with(&refund, &merchant, &100, &options);
+        
+        assert_eq!(client.get_session(&refund).unwrap().refund_of, Some(original.clone()));
+        assert_eq!(client.get_refunds_for(&original), Vec::from_array(&env, [refund]));
+        
+        // A refund can't reference an unknown session
+        let options = SessionOptions {
+            refund_of: Some(String::from_str(&env, "pay_unknown")),
+            ..Default::default()
+        };
+        assert_eq!(
+            client.try_register_session_with(&String::from_str(&env, "ref_unknown"), &merchant, &100, &options),
+            Err(Ok(Error::SessionNotFound))
+        );
+    }
+
+    #[test]
+    fn test_completion_threshold() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_threshold");
+        let options = SessionOptions { completion_threshold_bps: 9_000, ..Default::default() };
+        client.register_session_with(&memo, &merchant, &100, &options);
+        
+        client.record_partial_payment(&memo, &50);
+        client.record_partial_payment(&memo, &39);
+        assert_eq!(client.get_session(&memo).unwrap().status, SessionStatus::Active);
+        client.record_partial_payment(&memo, &1);
+        
+        let session = client.get_session(&memo).unwrap();
+        assert_eq!(session.status, SessionStatus::Completed);
+        assert_eq!(session.paid_so_far, 90);
+        
+        // Default threshold is the full amount; above 100% is rejected
+        let full = String::from_str(&env, "pay_threshold_full");
+        client.register_session(&full, &merchant, &100);
+        assert_eq!(client.get_session(&full).unwrap().completion_threshold_bps, MAX_BPS);
+        let options = SessionOptions { completion_threshold_bps: 10_001, ..Default::default() };
+        assert_eq!(
+            client.try_register_session_with(&String::from_str(&env, "pay_bad_th"), &merchant, &100, &options),
+            Err(Ok(Error::InvalidBps))
+        );
+    }
+
+    #[test]
+    fn test_check_invariants() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        assert!(client.check_invariants());
+        
+        for memo in ["pay_inv_1", "pay_inv_2", "pay_inv_3"] {
+            client.register_session(&String::from_str(&env, memo), &merchant, &100);
+        }
+        client.validate_payment(&String::from_str(&env, "pay_inv_1"), &100);
+        client.deactivate_session(&String::from_str(&env, "pay_inv_2"));
+        assert!(client.check_invariants());
+        
+        // Corrupt the global active counter
+        env.as_contract(&client.address, || {
+            env.storage().instance().set(&DataKey::ActiveCount, &5u64);
+        });
+        assert!(!client.check_invariants());
+    }
+
+    #[test]
+    fn test_completed_at() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        env.ledger().with_mut(|li| li.timestamp = 1_000);
+        
+        let memo = String::from_str(&env, "pay_completed_at");
+        client.register_session(&memo, &merchant, &100);
+        assert_eq!(client.get_session(&memo).unwrap().completed_at, None);
+        
+        env.ledger().with_mut(|li| li.timestamp = 1_234);
+        client.validate_payment(&memo, &100);
+        let session = client.get_session(&memo).unwrap();
+        assert_eq!(session.created_at, 1_000);
+        assert_eq!(session.completed_at, Some(1_234));
+    }
+
+    #[test]
+    fn test_set_max_sessions_per_merchant() {
+        let env = Env::default();
+        let config = InitConfig { max_sessions_per_merchant: 1, ..Default::default() };
+        let (client, _, _) = setup_with(&env, config);
+        let merchant = Address::generate(&env);
+        
+        client.register_session(&String::from_str(&env, "pay_lim_1"), &merchant, &100);
+        let second = String::from_str(&env, "pay_lim_2");
+        assert_eq!(
+            client.try_register_session(&second, &merchant, &100),
+            Err(Ok(Error::MerchantLimitReached))
+        );
+        
+        // Raising the limit allows more registrations
+        client.set_max_sessions_per_merchant(&2);
+        client.register_session(&second, &merchant, &100);
+        let third = String::from_str(&env, "pay_lim_3");
+        assert_eq!(
+            client.try_register_session(&third, &merchant, &100),
+            Err(Ok(Error::MerchantLimitReached))
+        );
+        
+        // Zero disables the limit
+        client.set_max_sessions_per_merchant(&0);
+        client.register_session(&third, &merchant, &100);
+    }
+
+    #[test]
+    fn test_get_sessions_by_asset() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let usdc = Address::generate(&env);
+        let eurc = Address::generate(&env);
+        
+        let register = |memo: &str, asset: &Address| {
+            let options = SessionOptions { asset: Some(asset.clone()), ..Default::default() };
+            client.register_session_with(&String::from_str(&env, memo), &merchant, &100, &options);
+            String::from_str(&env, memo)
+        };
+        let usdc_1 = register("pay_usdc_1", &usdc);
+        let usdc_2 = register("pay_usdc_2", &usdc);
+        let usdc_3 = register("pay_usdc_3", &usdc);
+        let eurc_1 = register("pay_eurc_1", &eurc);
+        
+        assert_eq!(
+            client.get_sessions_by_asset(&usdc, &0, &10),
+            Vec::from_array(&env, [usdc_1.clone(), usdc_2.clone(), usdc_3.clone()])
+        );
+        assert_eq!(client.get_sessions_by_asset(&eurc, &0, &10), Vec::from_array(&env, [eurc_1]));
+        
+        // Pagination and closed sessions leaving the index
+        assert_eq!(client.get_sessions_by_asset(&usdc, &1, &1), Vec::from_array(&env, [usdc_2.clone()]));
+        assert!(client.get_sessions_by_asset(&usdc, &5, &10).is_empty());
+        client.validate_payment(&usdc_1, &100);
+        assert_eq!(
+            client.get_sessions_by_asset(&usdc, &0, &10),
+            Vec::from_array(&env, [usdc_3, usdc_2])
+        );
+    }
+
+    #[test]
+    fn test_fee_schedule() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        // 3% below 10_000, 1% from 10_000 up
+        let tiers = Vec::from_array(&env, [
+            FeeTier { min_amount: 0, bps: 300 },
+            FeeTier { min_amount: 10_000, bps: 100 },
+        ]);
+        client.set_fee_schedule(&tiers);
+        
+        let small = String::from_str(&env, "pay_tier_small");
+        client.register_session(&small, &merchant, &9_999);
+        client.validate_payment(&small, &9_999);
+        assert_eq!(client.get_session(&small).unwrap().fee, 299);
+        
+        let large = String::from_str(&env, "pay_tier_large");
+        client.register_session(&large, &merchant, &10_000);
+        client.validate_payment(&large, &10_000);
+        assert_eq!(client.get_session(&large).unwrap().fee, 100);
+        
+        // Unsorted schedules and out-of-range bps are rejected
+        let unsorted = Vec::from_array(&env, [
+            FeeTier { min_amount: 10_000, bps: 100 },
+            FeeTier { min_amount: 0, bps: 300 },
+        ]);
+        assert_eq!(client.try_set_fee_schedule(&unsorted), Err(Ok(Error::InvalidFeeSchedule)));
+        let too_high = Vec::from_array(&env, [FeeTier { min_amount: 0, bps: 10_001 }]);
+        assert_eq!(client.try_set_fee_schedule(&too_high), Err(Ok(Error::InvalidBps)));
+    }
+
+    #[test]
+    fn test_duplicate_payer_validation() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let payer = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_dupe");
+        client.register_session(&memo, &merchant, &100);
+        assert!(client.validate_payment_full(&memo, &100, &payer));
+        let completed = client.get_session(&memo).unwrap();
+        
+        // Same payer retrying is an idempotent success
+        assert!(client.validate_payment_full(&memo, &100, &payer));
+        assert_eq!(client.get_session(&memo).unwrap(), completed);
+        
+        // A different payer can't claim the completed session
+        let other = Address::generate(&env);
+        assert_eq!(
+            client.try_validate_payment_full(&memo, &100, &other),
+            Err(Ok(Error::AlreadyCompleted))
+        );
+        
+        // Once refunded or disputed, a retry no longer reports success
+        client.mark_refunded(&memo);
+        assert_eq!(
+            client.try_validate_payment_full(&memo, &100, &payer),
+            Err(Ok(Error::InvalidStatus))
+        );
+        let disputed = String::from_str(&env, "pay_dupe_disputed");
+        client.register_session(&disputed, &merchant, &100);
+        client.validate_payment_full(&disputed, &100, &payer);
+        client.open_dispute(&disputed);
+        assert_eq!(
+            client.try_validate_payment_full(&disputed, &100, &payer),
+            Err(Ok(Error::InvalidStatus))
+        );
+    }
+
+    #[test]
+    fn test_tick_size() {
+        let env = Env::default();
+        let config = InitConfig { tick_size: 100, ..Default::default() };
+        let (client, _, _) = setup_with(&env, config);
+        let merchant = Address::generate(&env);
+        
+        client.register_session(&String::from_str(&env, "pay_tick_ok"), &merchant, &1_500);
+        assert_eq!(
+            client.try_register_session(&String::from_str(&env, "pay_tick_bad"), &merchant, &1_550),
+            Err(Ok(Error::InvalidAmount))
+        );
+        
+        // Default tick accepts any amount
+        let (client, _, _) = setup(&env);
+        client.register_session(&String::from_str(&env, "pay_tick_any"), &merchant, &1_551);
+    }
+
+    #[test]
+    fn test_get_merchant_totals() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let first = Address::generate(&env);
+        let second = Address::generate(&env);
+        let unseen = Address::generate(&env);
+        
+        for (memo, merchant, amount) in [
+            ("pay_tot_1", &first, 100),
+            ("pay_tot_2", &first, 150),
+            ("pay_tot_3", &second, 70),
+        ] {
+            let memo = String::from_str(&env, memo);
+            client.register_session(&memo, merchant, &amount);
+            client.validate_payment(&memo, &amount);
+        }
+        
+        let merchants = Vec::from_array(&env, [first, second, unseen]);
+        assert_eq!(client.get_merchant_totals(&merchants), Vec::from_array(&env, [250i128, 70, 0]));
+        
+        let mut too_many = Vec::new(&env);
+        for _ in 0..=MAX_BATCH_SIZE {
+            too_many.push_back(Address::generate(&env));
+        }
+        assert_eq!(client.try_get_merchant_totals(&too_many), Err(Ok(Error::BatchTooLarge)));
+    }
+
+    #[test]
+    fn test_register_signed_session() {
+        use ed25519_dalek::{Signer, SigningKey};
+        
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let signing_key = SigningKey::from_bytes(&[7u8; 32]);
+        let public_key = BytesN::from_array(&env, &signing_key.verifying_key().to_bytes());
+        client.set_merchant_key(&merchant, &public_key);
+        
+        let sign = |order: &SignedOrder| {
+            let bytes = order.clone().to_xdr(&env);
+            let mut buf = [0u8; 128];
+            let len = bytes.len() as usize;
+            bytes.copy_into_slice(&mut buf[..len]);
+            let signature = BytesN::from_array(&env, &signing_key.sign(&buf[..len]).to_bytes());
+            (bytes, signature)
+        };
+        
+        // Valid signature registers the signed memo and amount
+        let order = SignedOrder { memo: String::from_str(&env, "pay_signed"), amount: 500 };
+        let (bytes, signature) = sign(&order);
+        client.register_signed_session(&bytes, &merchant, &signature);
+        let session = client.get_session(&order.memo).unwrap();
+        assert_eq!(session.amount, 500);
+        assert_eq!(session.merchant, merchant);
+        
+        // Tampered payload fails verification
+        let tampered = SignedOrder { memo: String::from_str(&env, "pay_tampered"), amount: 5 };
+        let (_, signature) = sign(&SignedOrder { memo: tampered.memo.clone(), amount: 500 });
+        assert!(client
+            .try_register_signed_session(&tampered.to_xdr(&env), &merchant, &signature)
+            .is_err());
+        assert!(client.get_session(&String::from_str(&env, "pay_tampered")).is_none());
+        
+        // Merchant without a registered key
+        let unknown = Address::generate(&env);
+        assert_eq!(
+            client.try_register_signed_session(&bytes, &unknown, &signature),
+            Err(Ok(Error::BadSignature))
+        );
+    }
+
+    #[test]
+    fn test_get_overpayment() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_surplus");
+        client.register_session(&memo, &merchant, &100);
+        assert_eq!(client.try_get_overpayment(&memo), Err(Ok(Error::NotCompleted)));
+        
+        client.validate_payment(&memo, &125);
+        assert_eq!(client.get_overpayment(&memo), 25);
+        
+        let exact = String::from_str(&env, "pay_no_surplus");
+        client.register_session(&exact, &merchant, &100);
+        client.validate_payment(&exact, &100);
+        assert_eq!(client.get_overpayment(&exact), 0);
+    }
+
+    #[test]
+    fn test_actual_amount_recorded() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_actual");
+        client.register_session(&memo, &merchant, &100);
+        assert_eq!(client.get_session(&memo).unwrap().actual_amount, None);
+        
+        client.validate_payment(&memo, &140);
+        let (_, _, data) = env.events().all().last().unwrap();
+        let (_, event): (u64, CompletionEvent) = data.into_val(&env);
+        assert_eq!(event.amount, 100);
+        assert_eq!(event.actual_amount, 140);
+        
+        let session = client.get_session(&memo).unwrap();
+        assert_eq!(session.amount, 100);
+        assert_eq!(session.actual_amount, Some(140));
+    }
+
+    #[test]
+    fn test_registration_cooldown() {
+        let env = Env::default();
+        let config = InitConfig { registration_cooldown_seconds: 60, ..Default::default() };
+        let (client, _, _) = setup_with(&env, config);
+        let merchant = Address::generate(&env);
+        env.ledger().with_mut(|li| li.timestamp = 1000);
+        
+        client.register_session(&String::from_str(&env, "pay_cool_1"), &merchant, &100);
+        let second = String::from_str(&env, "pay_cool_2");
+        assert_eq!(
+            client.try_register_session(&second, &merchant, &100),
+            Err(Ok(Error::RateLimited))
+        );
+        
+        // Other merchants aren't throttled
+        client.register_session(&String::from_str(&env, "pay_cool_3"), &Address::generate(&env), &100);
+        
+        env.ledger().with_mut(|li| li.timestamp = 1060);
+        client.register_session(&second, &merchant, &100);
+        assert!(client.get_session(&second).is_some());
+    }
+
+    #[test]
+    fn test_event_seq_consecutive() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_seq");
+        client.register_session(&memo, &merchant, &100);
+        let (_, _, data) = env.events().all().last().unwrap();
+        let (first, _): (u64, String) = data.into_val(&env);
+        
+        client.validate_payment(&memo, &100);
+        let (_, _, data) = env.events().all().last().unwrap();
+        let (second, _): (u64, CompletionEvent) = data.into_val(&env);
+        assert_eq!(second, first + 1);
+    }
+
+    #[test]
+    fn test_reassign_merchant() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let old_merchant = Address::generate(&env);
+        let new_merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_moved");
+        client.register_session(&memo, &old_merchant, &100);
+        client.reassign_merchant(&memo, &new_merchant);
+        assert_eq!(client.get_session(&memo).unwrap().merchant, new_merchant);
+        
+        let (merchants, _) = client.get_active_merchants();
+        assert_eq!(merchants, Vec::from_array(&env, [new_merchant.clone()]));
+        env.as_contract(&client.address, || {
+            assert_eq!(merchant_active_count(&env, &old_merchant), 0);
+            assert_eq!(merchant_active_count(&env, &new_merchant), 1);
+            let index = Index::Merchant(new_merchant.clone());
+            assert_eq!(paged_range(&env, &index, 0, 10), Vec::from_array(&env, [memo.clone()]));
+            assert_eq!(paged_len(&env, &Index::Merchant(old_merchant.clone())), 0);
+        });
+        
+        // Partially paid and completed sessions stay with their merchant
+        let partial = String::from_str(&env, "pay_part_moved");
+        client.register_session(&partial, &old_merchant, &100);
+        client.record_partial_payment(&partial, &40);
+        assert_eq!(
+            client.try_reassign_merchant(&partial, &new_merchant),
+            Err(Ok(Error::InvalidStatus))
+        );
+        client.validate_payment(&memo, &100);
+        assert_eq!(
+            client.try_reassign_merchant(&memo, &old_merchant),
+            Err(Ok(Error::InvalidStatus))
+        );
+    }
+
+    #[test]
+    fn test_session_flags() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        assert_eq!(client.session_flags(&String::from_str(&env, "pay_nothing")), 0);
+        
+        let active = String::from_str(&env, "pay_flag_active");
+        client.register_session(&active, &merchant, &100);
+        assert_eq!(client.session_flags(&active), FLAG_EXISTS | FLAG_ACTIVE);
+        
+        let completed = String::from_str(&env, "pay_flag_done");
+        client.register_session(&completed, &merchant, &100);
+        client.validate_payment(&completed, &100);
+        assert_eq!(client.session_flags(&completed), FLAG_EXISTS | FLAG_COMPLETED);
+        
+        let refunded = String::from_str(&env, "pay_flag_refund");
+        let options = SessionOptions { escrow: true, ..Default::default() };
+        client.register_session_with(&refunded, &merchant, &100, &options);
+        client.validate_payment(&refunded, &100);
+        client.refund_escrow(&refunded);
+        assert_eq!(client.session_flags(&refunded), FLAG_EXISTS | FLAG_REFUNDED);
+    }
+
+    #[test]
+    fn test_register_and_validate() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_instant");
+        let session = client.register_and_validate(&memo, &merchant, &100, &100);
+        assert_eq!(session.status, SessionStatus::Completed);
+        assert_eq!(client.get_session(&memo).unwrap().actual_amount, Some(100));
+        
+        let short = String::from_str(&env, "pay_instant_short");
+        assert_eq!(
+            client.try_register_and_validate(&short, &merchant, &100, &99),
+            Err(Ok(Error::InsufficientAmount))
+        );
+        assert!(client.get_session(&short).is_none());
+        assert_eq!(client.get_session_count(), 1);
+    }
+
+    #[test]
+    fn test_storage_keys_do_not_collide() {
+        let env = Env::default();
+        let (client, backend, admin) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        // Memos spelling out config and index names don't touch those entries
+        for memo in ["backend", "admin", "sess_ct", "active", "mer_idx", "idx"] {
+            client.register_session(&String::from_str(&env, memo), &merchant, &100);
+        }
+        let controllers = client.get_controllers();
+        assert_eq!(controllers.backend, backend);
+        assert_eq!(controllers.admin, Some(admin));
+        assert_eq!(client.get_session_count(), 6);
+        assert_eq!(client.get_session_by_index(&5).unwrap().memo, String::from_str(&env, "idx"));
+        
+        env.as_contract(&client.address, || {
+            assert_eq!(active_count(&env), 6);
+            assert_eq!(paged_len(&env, &Index::Merchant(merchant.clone())), 6);
+        });
+        let backend_session = client.get_session(&String::from_str(&env, "backend")).unwrap();
+        assert_eq!(backend_session.merchant, merchant);
+        assert!(client.check_invariants());
+    }
+
+    #[test]
+    fn test_check_amount() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_check");
+        let options = SessionOptions { ttl_seconds: 60, ..Default::default() };
+        client.register_session_with(&memo, &merchant, &100, &options);
+        assert_eq!(client.check_amount(&memo, &100), 0);
+        assert_eq!(client.check_amount(&memo, &150), 0);
+        assert_eq!(client.check_amount(&memo, &70), 30);
+        assert!(client.get_session(&memo).unwrap().is_active);
+        
+        env.ledger().with_mut(|li| li.timestamp += 60);
+        assert_eq!(client.try_check_amount(&memo, &100), Err(Ok(Error::SessionExpired)));
+        assert_eq!(
+            client.try_check_amount(&String::from_str(&env, "pay_unknown"), &100),
+            Err(Ok(Error::SessionNotFound))
+        );
+    }
+
+    #[test]
+    fn test_fee_exempt_merchant() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let partner = Address::generate(&env);
+        let regular = Address::generate(&env);
+        client.set_global_fee_bps(&250);
+        client.set_fee_exempt(&partner, &true);
+        
+        let options = SessionOptions { fee_bps: Some(100), ..Default::default() };
+        let exempt = String::from_str(&env, "pay_partner");
+        client.register_session_with(&exempt, &partner, &10_000, &options);
+        client.validate_payment(&exempt, &10_000);
+        assert_eq!(client.get_session(&exempt).unwrap().fee, 0);
+        
+        let normal = String::from_str(&env, "pay_regular");
+        client.register_session_with(&normal, &regular, &10_000, &options);
+        client.validate_payment(&normal, &10_000);
+        assert_eq!(client.get_session(&normal).unwrap().fee, 100);
+        
+        // Lifting the exemption restores normal fees
+        client.set_fee_exempt(&partner, &false);
+        let later = String::from_str(&env, "pay_partner_2");
+        client.register_session(&later, &partner, &10_000);
+        client.validate_payment(&later, &10_000);
+        assert_eq!(client.get_session(&later).unwrap().fee, 250);
+    }
+
+    #[test]
+    fn test_authorize_then_capture() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_auth");
+        client.register_session(&memo, &merchant, &100);
+        client.authorize(&memo, &120);
+        let session = client.get_session(&memo).unwrap();
+        assert_eq!(session.status, SessionStatus::Authorized);
+        assert!(!session.is_active);
+        assert_eq!(client.try_validate_payment(&memo, &100), Err(Ok(Error::SessionExpired)));
+        
+        assert!(client.capture(&memo));
+        let session = client.get_session(&memo).unwrap();
+        assert_eq!(session.status, SessionStatus::Completed);
+        assert_eq!(session.actual_amount, Some(120));
+        assert_eq!(client.get_merchant_total(&merchant), 120);
+        assert!(client.check_invariants());
+        assert_eq!(client.try_capture(&memo), Err(Ok(Error::InvalidStatus)));
+    }
+
+    #[test]
+    fn test_authorize_then_void() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_void");
+        client.register_session(&memo, &merchant, &100);
+        assert_eq!(client.try_authorize(&memo, &50), Err(Ok(Error::InsufficientAmount)));
+        client.authorize(&memo, &100);
+        client.void(&memo);
+        assert_eq!(client.get_session(&memo).unwrap().status, SessionStatus::Cancelled);
+        assert_eq!(client.get_merchant_total(&merchant), 0);
+        assert!(client.check_invariants());
+        
+        // A lapsed authorization is voided instead of captured
+        let lapsed = String::from_str(&env, "pay_lapsed");
+        client.register_session(&lapsed, &merchant, &100);
+        client.authorize(&lapsed, &100);
+        env.ledger().with_mut(|li| li.timestamp += AUTH_WINDOW_SECONDS);
+        assert!(!client.capture(&lapsed));
+        assert_eq!(client.get_session(&lapsed).unwrap().status, SessionStatus::Cancelled);
+    }
+
+    #[test]
+    fn test_error_catalog() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        
+        let catalog = client.error_catalog();
+        assert_eq!(catalog.len() as usize, ERROR_CATALOG.len());
+        assert!(catalog.contains((Error::InvalidMemo as u32, Symbol::new(&env, "InvalidMemo"))));
+        assert!(catalog.contains((Error::SessionNotFound as u32, Symbol::new(&env, "SessionNotFound"))));
+        assert!(catalog.contains((Error::RateLimited as u32, Symbol::new(&env, "RateLimited"))));
+        assert_eq!(catalog.get(0).unwrap().0, 1);
+    }
+
+    #[test]
+    fn test_confirmations_recorded() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_confirmed");
+        let options = SessionOptions { min_confirmations: 3, ..Default::default() };
+        client.register_session_with(&memo, &merchant, &100, &options);
+        client.validate_with_confirmations(&memo, &100, &5);
+        
+        let (_, _, data) = env.events().all().last().unwrap();
+        let (_, event): (u64, CompletionEvent) = data.into_val(&env);
+        assert_eq!(event.confirmations, 5);
+        let session = client.get_session(&memo).unwrap();
+        assert_eq!(session.min_confirmations, 3);
+        assert_eq!(session.confirmations_at_validation, 5);
+    }
+
+    #[test]
+    fn test_close_session() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_close");
+        client.register_session(&memo, &merchant, &100);
+        client.close_session(&memo);
+        assert!(client.get_session(&memo).is_none());
+        let (merchants, _) = client.get_active_merchants();
+        assert!(merchants.is_empty());
+        assert!(client.check_invariants());
+        
+        // The memo can't be reused, and completed sessions aren't closed
+        assert_eq!(
+            client.try_register_session(&memo, &merchant, &100),
+            Err(Ok(Error::SessionAlreadyExists))
+        );
+        let done = String::from_str(&env, "pay_close_done");
+        client.register_session(&done, &merchant, &100);
+        client.validate_payment(&done, &100);
+        assert_eq!(client.try_close_session(&done), Err(Ok(Error::AlreadyInactive)));
+    }
+
+    #[test]
+    fn test_custom_registration_topic() {
+        let env = Env::default();
+        let merchant = Address::generate(&env);
+        let memo = String::from_str(&env, "pay_reg_topic");
+        
+        let (client, _, _) = setup(&env);
+        client.register_session(&memo, &merchant, &100);
+        let (_, topics, _) = env.events().all().last().unwrap();
+        assert_eq!(topics, (symbol_short!("reg_sess"),).into_val(&env));
+        
+        let config = InitConfig { registration_topic: Some(symbol_short!("stg_reg")), ..Default::default() };
+        let (client, _, _) = setup_with(&env, config);
+        client.register_session(&memo, &merchant, &100);
+        let (_, topics, _) = env.events().all().last().unwrap();
+        assert_eq!(topics, (symbol_short!("stg_reg"),).into_val(&env));
+    }
+
+    #[test]
+    fn test_get_session_by_ref() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let external_ref = String::from_str(&env, "ORDER-1001");
+        let options = SessionOptions { external_ref: Some(external_ref.clone()), ..Default::default() };
+        let memo = String::from_str(&env, "pay_ref");
+        client.register_session_with(&memo, &merchant, &100, &options);
+        
+        let session = client.get_session_by_ref(&external_ref).unwrap();
+        assert_eq!(session.memo, memo);
+        assert_eq!(session.external_ref, Some(external_ref));
+        assert!(client.get_session_by_ref(&String::from_str(&env, "ORDER-404")).is_none());
+        
+        let other = String::from_str(&env, "pay_ref_dup");
+        assert_eq!(
+            client.try_register_session_with(&other, &merchant, &100, &options),
+            Err(Ok(Error::DuplicateReference))
+        );
+    }
+
+    #[test]
+    fn test_preview_fee() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let partner = Address::generate(&env);
+        client.set_global_fee_bps(&250);
+        client.set_fee_exempt(&partner, &true);
+        client.set_fee_schedule(&Vec::from_array(&env, [FeeTier { min_amount: 1_000_000, bps: 100 }]));
+        
+        assert_eq!(client.preview_fee(&merchant, &10_000), 250);
+        assert_eq!(client.preview_fee(&partner, &10_000), 0);
+        assert_eq!(client.preview_fee(&merchant, &1_000_000), 10_000);
+        
+        // Preview matches what validation charges
+        let memo = String::from_str(&env, "pay_preview");
+        client.register_session(&memo, &merchant, &10_000);
+        client.validate_payment(&memo, &10_000);
+        assert_eq!(client.get_session(&memo).unwrap().fee, 250);
+    }
+
+    #[test]
+    fn test_pause_registration() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let in_flight = String::from_str(&env, "pay_in_flight");
+        client.register_session(&in_flight, &merchant, &100);
+        client.pause_registration();
+        
+        let new = String::from_str(&env, "pay_new");
+        assert_eq!(
+            client.try_register_session(&new, &merchant, &100),
+            Err(Ok(Error::ContractPaused))
+        );
+        assert!(client.validate_payment(&in_flight, &100));
+        
+        client.unpause_registration();
+        client.register_session(&new, &merchant, &100);
+    }
+
+    #[test]
+    fn test_get_session_live() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_live");
+        let options = SessionOptions { ttl_seconds: 60, ..Default::default() };
+        client.register_session_with(&memo, &merchant, &100, &options);
+        assert_eq!(client.get_session_live(&memo).unwrap().status, SessionStatus::Active);
+        
+        env.ledger().with_mut(|li| li.timestamp += 60);
+        let view = client.get_session_live(&memo).unwrap();
+        assert_eq!(view.status, SessionStatus::Expired);
+        assert_eq!(view.session.status, SessionStatus::Active);
+        assert_eq!(client.get_session(&memo).unwrap().status, SessionStatus::Active);
+    }
+
+    #[test]
+    fn test_allowed_payers() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let alice = Address::generate(&env);
+        let bob = Address::generate(&env);
+        let mallory = Address::generate(&env);
+        
+        let payers = Vec::from_array(&env, [alice.clone(), bob.clone()]);
+        let options = SessionOptions { allowed_payers: Some(payers), ..Default::default() };
+        let shared = String::from_str(&env, "pay_shared");
+        client.register_session_with(&shared, &merchant, &100, &options);
+        assert_eq!(
+            client.try_validate_payment_full(&shared, &100, &mallory),
+            Err(Ok(Error::WrongPayer))
+        );
+        assert_eq!(client.try_validate_payment(&shared, &100), Err(Ok(Error::WrongPayer)));
+        
+        // Backend paths that name no payer can't complete it either
+        assert_eq!(client.try_record_partial_payment(&shared, &100), Err(Ok(Error::WrongPayer)));
+        assert_eq!(client.try_authorize(&shared, &100), Err(Ok(Error::WrongPayer)));
+        assert_eq!(client.get_session(&shared).unwrap().status, SessionStatus::Active);
+        
+        assert!(client.validate_payment_full(&shared, &100, &bob));
+        assert_eq!(client.get_payer(&shared), Some(bob));
+        
+        // No list: anyone can pay
+        let open = String::from_str(&env, "pay_open");
+        client.register_session(&open, &merchant, &100);
+        assert!(client.validate_payment_full(&open, &100, &mallory));
+        
+        let mut crowd = Vec::new(&env);
+        for _ in 0..=MAX_ALLOWED_PAYERS {
+            crowd.push_back(Address::generate(&env));
+        }
+        let options = SessionOptions { allowed_payers: Some(crowd), ..Default::default() };
+        assert_eq!(
+            client.try_register_session_with(&String::from_str(&env, "pay_crowd"), &merchant, &100, &options),
+            Err(Ok(Error::TooManyPayers))
+        );
+    }
+
+    #[test]
+    fn test_snapshot_stats() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        client.set_global_fee_bps(&100);
+        
+        let first = String::from_str(&env, "pay_period_1");
+        client.register_session(&first, &merchant, &1_000);
+        client.validate_payment(&first, &1_000);
+        client.register_session(&String::from_str(&env, "pay_period_open"), &merchant, &500);
+        client.snapshot_stats(&202610);
+        assert_eq!(client.try_snapshot_stats(&202610), Err(Ok(Error::SnapshotExists)));
+        
+        let second = String::from_str(&env, "pay_period_2");
+        client.register_session(&second, &merchant, &2_000);
+        client.validate_payment(&second, &2_000);
+        
+        let frozen = ContractStats { sessions: 2, active: 1, completed: 1, volume: 1_000, fees: 10 };
+        assert_eq!(client.get_snapshot(&202610), Some(frozen));
+        let live = ContractStats { sessions: 3, active: 1, completed: 2, volume: 3_000, fees: 30 };
+        assert_eq!(client.get_stats(), live);
+        assert_eq!(client.get_snapshot(&202611), None);
+    }
+
+    #[test]
+    fn test_already_completed_vs_expired() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let paid = String::from_str(&env, "pay_twice");
+        client.register_session(&paid, &merchant, &100);
+        client.validate_payment(&paid, &100);
+        assert_eq!(client.try_validate_payment(&paid, &100), Err(Ok(Error::AlreadyCompleted)));
+        
+        let lapsed = String::from_str(&env, "pay_lapsed_ttl");
+        let options = SessionOptions { ttl_seconds: 60, ..Default::default() };
+        client.register_session_with(&lapsed, &merchant, &100, &options);
+        env.ledger().with_mut(|li| li.timestamp += 60);
+        assert_eq!(client.try_validate_payment(&lapsed, &100), Err(Ok(Error::SessionExpired)));
+    }
+
+    #[test]
+    fn test_init_approved_merchants() {
+        let env = Env::default();
+        let first = Address::generate(&env);
+        let second = Address::generate(&env);
+        let merchants = Vec::from_array(&env, [first.clone(), second.clone()]);
+        let config = InitConfig { approved_merchants: Some(merchants), ..Default::default() };
+        let (client, _, _) = setup_with(&env, config);
+        
+        client.register_session(&String::from_str(&env, "pay_seed_1"), &first, &100);
+        client.register_session(&String::from_str(&env, "pay_seed_2"), &second, &100);
+        
+        let outsider = Address::generate(&env);
+        let memo = String::from_str(&env, "pay_outsider");
+        assert_eq!(
+            client.try_register_session(&memo, &outsider, &100),
+            Err(Ok(Error::MerchantNotApproved))
+        );
+        client.approve_merchant(&outsider);
+        client.register_session(&memo, &outsider, &100);
+    }
+
+    #[test]
+    fn test_now() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        env.ledger().with_mut(|li| li.timestamp = 1_760_000_000);
+        assert_eq!(client.now(), 1_760_000_000);
+    }
+
+    #[test]
+    fn test_min_session_age() {
+        let env = Env::default();
+        let config = InitConfig { min_age_seconds: 30, ..Default::default() };
+        let (client, _, _) = setup_with(&env, config);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_young");
+        client.register_session(&memo, &merchant, &100);
+        assert_eq!(client.try_validate_payment(&memo, &100), Err(Ok(Error::SessionTooNew)));
+        
+        env.ledger().with_mut(|li| li.timestamp += 30);
+        assert!(client.validate_payment(&memo, &100));
+    }
+
+    #[test]
+    fn test_recent_completions() {
+        let env = Env::default();
+        env.budget().reset_unlimited();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memos = [
+            "pay_r00", "pay_r01", "pay_r02", "pay_r03", "pay_r04", "pay_r05", "pay_r06",
+            "pay_r07", "pay_r08", "pay_r09", "pay_r10", "pay_r11", "pay_r12", "pay_r13",
+            "pay_r14", "pay_r15", "pay_r16", "pay_r17", "pay_r18", "pay_r19", "pay_r20",
+            "pay_r21",
+        ];
+        for (i, memo) in memos.iter().enumerate() {
+            let memo = String::from_str(&env, memo);
+            client.register_session(&memo, &merchant, &100);
+            client.validate_payment(&memo, &(100 + i as i128));
+        }
+        
+        let recent = client.recent_completions();
+        assert_eq!(recent.len(), MAX_RECENT_COMPLETIONS);
+        let oldest = recent.first().unwrap();
+        assert_eq!(oldest.memo, String::from_str(&env, "pay_r02"));
+        assert_eq!(oldest.amount, 102);
+        let newest = recent.last().unwrap();
+        assert_eq!(newest.memo, String::from_str(&env, "pay_r21"));
+        assert_eq!(newest.merchant, merchant);
+    }
+
+    #[test]
+    fn test_partial_not_allowed() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let ticket = String::from_str(&env, "pay_ticket");
+        let options = SessionOptions { allow_partial: Some(false), ..Default::default() };
+        client.register_session_with(&ticket, &merchant, &100, &options);
+        assert_eq!(
+            client.try_record_partial_payment(&ticket, &40),
+            Err(Ok(Error::PartialNotAllowed))
+        );
+        assert!(client.validate_payment(&ticket, &100));
+        
+        let invoice = String::from_str(&env, "pay_invoice");
+        client.register_session(&invoice, &merchant, &100);
+        client.record_partial_payment(&invoice, &40);
+        assert_eq!(client.get_session(&invoice).unwrap().paid_so_far, 40);
+    }
+
+    #[test]
+    fn test_total_outstanding() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let paid = String::from_str(&env, "pay_out_1");
+        let cancelled = String::from_str(&env, "pay_out_2");
+        client.register_session(&paid, &merchant, &100);
+        client.register_session(&cancelled, &merchant, &250);
+        client.register_session(&String::from_str(&env, "pay_out_3"), &merchant, &400);
+        assert_eq!(client.get_total_outstanding(), 750);
+        
+        client.validate_payment(&paid, &120);
+        client.deactivate_session(&cancelled);
+        assert_eq!(client.get_total_outstanding(), 400);
+        
+        // Nothing can be expected at zero or below
+        for amount in [0, -100] {
+            assert_eq!(
+                client.try_register_session(&String::from_str(&env, "pay_out_4"), &merchant, &amount),
+                Err(Ok(Error::InvalidAmount))
+            );
+        }
+        assert_eq!(client.get_total_outstanding(), 400);
+    }
+
+    #[test]
+    fn test_hash_memo_session() {
+        let env = Env::default();
+        let (client, backend, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo_hash = BytesN::from_array(&env, &[0xab; 32]);
+        client.register_session_hash(&memo_hash, &merchant, &100);
+        assert_eq!(
+            client.try_register_session_hash(&memo_hash, &merchant, &100),
+            Err(Ok(Error::SessionAlreadyExists))
+        );
+        let session = client.get_session_by_hash(&memo_hash).unwrap();
+        assert_eq!(session.memo, String::from_str(&env, "h_ababababababababababab"));
+        
+        assert!(client.validate_payment_hash(&memo_hash, &100));
+        assert_eq!(env.auths()[0].0, backend);
+        assert_eq!(client.get_session_by_hash(&memo_hash).unwrap().status, SessionStatus::Completed);
+        
+        let unknown = BytesN::from_array(&env, &[0x01; 32]);
+        assert_eq!(client.try_validate_payment_hash(&unknown, &100), Err(Ok(Error::SessionNotFound)));
+    }
+
+    #[test]
+    fn test_validate_donation() {
+        let env = Env::default();
+        let config = InitConfig {
+            min_donation: 10,
+            registration_cooldown_seconds: 60,
+            ..Default::default()
+        };
+        let (client, _, _) = setup_with(&env, config);
+        let charity = Address::generate(&env);
+        let donor = Address::generate(&env);
+        assert_eq!(client.try_validate_donation(&50, &donor), Err(Ok(Error::NoDefaultMerchant)));
+        client.set_default_merchant(&charity);
+        
+        // Merchant registration gates don't hold donations back
+        client.set_memo_prefix(&charity, &Some(String::from_str(&env, "shop_")));
+        client.pause_registration();
+        
+        let first = client.validate_donation(&50, &donor);
+        let second = client.validate_donation(&25, &donor);
+        assert_eq!(first, String::from_str(&env, "don_0"));
+        assert_eq!(second, String::from_str(&env, "don_1"));
+        assert_eq!(client.try_validate_donation(&5, &donor), Err(Ok(Error::InsufficientAmount)));
+        
+        let session = client.get_session(&first).unwrap();
+        assert_eq!(session.merchant, charity);
+        assert_eq!(session.status, SessionStatus::Completed);
+        assert_eq!(session.actual_amount, Some(50));
+        assert_eq!(session.payer, Some(donor));
+        assert_eq!(client.get_merchant_total(&charity), 75);
+        
+        // while registrations stay paused
+        let order = String::from_str(&env, "shop_order");
+        assert_eq!(client.try_register_session(&order, &charity, &100), Err(Ok(Error::ContractPaused)));
+        client.unpause_registration();
+        client.register_session(&order, &charity, &100);
+    }
+
+    #[test]
+    fn test_reserved_memo_prefixes() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        client.set_default_merchant(&merchant);
+        
+        // Generated memos can't be claimed in any spelling
+        for memo in ["don_0", " DON_1", "h_ababababababababababab"] {
+            assert_eq!(
+                client.try_register_session(&String::from_str(&env, memo), &merchant, &100),
+                Err(Ok(Error::InvalidMemo))
+            );
+        }
+        
+        // so the contract can still generate them
+        assert_eq!(client.validate_donation(&50, &Address::generate(&env)), String::from_str(&env, "don_0"));
+        client.register_session_hash(&BytesN::from_array(&env, &[0xab; 32]), &merchant, &100);
+        
+        // Lookalikes outside the prefixes stay available
+        client.register_session(&String::from_str(&env, "donation_1"), &merchant, &100);
+        client.register_session(&String::from_str(&env, "hash_1"), &merchant, &100);
+    }
+
+    #[test]
+    fn test_session_state() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        assert_eq!(client.session_state(&String::from_str(&env, "pay_missing")), (false, false, false));
+        
+        let memo = String::from_str(&env, "pay_state");
+        let options = SessionOptions { ttl_seconds: 60, ..Default::default() };
+        client.register_session_with(&memo, &merchant, &100, &options);
+        assert_eq!(client.session_state(&memo), (true, true, false));
+        
+        env.ledger().with_mut(|li| li.timestamp += 60);
+        assert_eq!(client.session_state(&memo), (true, true, true));
+    }
+
+    #[test]
+    fn test_approve_merchants() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchants = Vec::from_array(
+            &env,
+            [Address::generate(&env), Address::generate(&env), Address::generate(&env)],
+        );
+        client.approve_merchant(&merchants.get(0).unwrap());
+        assert_eq!(client.approve_merchants(&merchants), 2);
+        
+        for (i, merchant) in merchants.iter().enumerate() {
+            let memo = String::from_str(&env, ["pay_bulk_0", "pay_bulk_1", "pay_bulk_2"][i]);
+            client.register_session(&memo, &merchant, &100);
+        }
+        assert_eq!(
+            client.try_register_session(&String::from_str(&env, "pay_bulk_x"), &Address::generate(&env), &100),
+            Err(Ok(Error::MerchantNotApproved))
+        );
+    }
+
+    #[test]
+    fn test_schema_version_check() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let memo = String::from_str(&env, "pay_schema");
+        client.register_session(&memo, &merchant, &100);
+        
+        // Simulate data written by a build that predates schema versioning
+        env.as_contract(&client.address, || {
+            env.storage().instance().remove(&DataKey::SchemaVersion);
+        });
+        let fresh = String::from_str(&env, "pay_schema_new");
+        assert_eq!(
+            client.try_register_session(&fresh, &merchant, &100),
+            Err(Ok(Error::SchemaMismatch))
+        );
+        assert_eq!(client.try_validate_payment(&memo, &100), Err(Ok(Error::SchemaMismatch)));
+        assert_eq!(client.try_set_global_fee_bps(&100), Err(Ok(Error::SchemaMismatch)));
+        
+        // Reads still work
+        assert!(client.get_session(&memo).is_some());
+    }
+
+    #[test]
+    fn test_extend_on_partial() {
+        let env = Env::default();
+        let config = InitConfig {
+            extend_on_partial_seconds: 600,
+            max_ttl_seconds: 1500,
+            ..Default::default()
+        };
+        let (client, _, _) = setup_with(&env, config);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_extend");
+        let options = SessionOptions { ttl_seconds: 600, ..Default::default() };
+        client.register_session_with(&memo, &merchant, &100, &options);
+        let created_at = client.get_session(&memo).unwrap().created_at;
+        
+        client.record_partial_payment(&memo, &10);
+        assert_eq!(client.get_session(&memo).unwrap().expires_at, Some(created_at + 1200));
+        
+        // Capped at max TTL from creation
+        client.record_partial_payment(&memo, &10);
+        assert_eq!(client.get_session(&memo).unwrap().expires_at, Some(created_at + 1500));
+    }
+
+    #[test]
+    fn test_last_activity() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        env.ledger().with_mut(|li| li.timestamp = 1000);
+        
+        let memo = String::from_str(&env, "pay_touched");
+        client.register_session(&memo, &merchant, &100);
+        assert_eq!(client.get_session(&memo).unwrap().last_activity, 1000);
+        
+        env.ledger().with_mut(|li| li.timestamp = 1300);
+        client.record_partial_payment(&memo, &40);
+        assert_eq!(client.get_session(&memo).unwrap().last_activity, 1300);
+        
+        env.ledger().with_mut(|li| li.timestamp = 1450);
+        client.validate_payment(&memo, &100);
+        assert_eq!(client.get_session(&memo).unwrap().last_activity, 1450);
+    }
+
+    #[test]
+    fn test_min_partial_bps() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_no_dust");
+        let options = SessionOptions { min_partial_bps: 1_000, ..Default::default() };
+        client.register_session_with(&memo, &merchant, &1_000, &options);
+        assert_eq!(client.try_record_partial_payment(&memo, &99), Err(Ok(Error::InvalidAmount)));
+        client.record_partial_payment(&memo, &100);
+        assert_eq!(client.get_session(&memo).unwrap().paid_so_far, 100);
+    }
+
+    #[test]
+    fn test_validate_with_receipt() {
+        let env = Env::default();
+        let (client, backend, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_receipt");
+        client.register_session(&memo, &merchant, &100);
+        env.ledger().with_mut(|li| li.timestamp += 30);
+        let (session, receipt) = client.validate_with_receipt(&memo, &140);
+        assert_eq!(env.auths()[0].0, backend);
+        assert_eq!(session.status, SessionStatus::Completed);
+        
+        let (_, _, data) = env.events().all().last().unwrap();
+        let (_, event): (u64, CompletionEvent) = data.into_val(&env);
+        assert_eq!(event.receipt, receipt);
+        
+        // The receipt binds what was paid and when
+        let completed_at = session.created_at + 30;
+        assert_eq!(session.completed_at, Some(completed_at));
+        let payload = (
+            memo.clone(),
+            merchant,
+            100i128,
+            None::<Address>,
+            session.created_at,
+            Some(140i128),
+            Some(completed_at),
+        );
+        let expected: BytesN<32> = env.crypto().sha256(&payload.to_xdr(&env)).to_bytes();
+        assert_eq!(receipt, expected);
+        assert_eq!(client.attest_completion(&memo), receipt);
+    }
+
+    #[test]
+    fn test_callback_retry() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let receiver = env.register_contract(None, CallbackReceiver);
+        
+        let memo = String::from_str(&env, "pay_callback");
+        let options = SessionOptions { callback: Some(receiver.clone()), ..Default::default() };
+        client.register_session_with(&memo, &merchant, &100, &options);
+        assert!(client.validate_payment(&memo, &100));
+        let session = client.get_session(&memo).unwrap();
+        assert_eq!(session.status, SessionStatus::Completed);
+        assert_eq!(session.callback_attempts, 1);
+        assert!(!session.callback_delivered);
+        
+        env.as_contract(&receiver, || {
+            env.storage().instance().set(&symbol_short!("READY"), &true);
+        });
+        assert!(client.retry_callback(&memo));
+        let session = client.get_session(&memo).unwrap();
+        assert_eq!(session.callback_attempts, 2);
+        assert!(session.callback_delivered);
+        env.as_contract(&receiver, || {
+            let last: String = env.storage().instance().get(&symbol_short!("LAST")).unwrap();
+            assert_eq!(last, memo);
+        });
+        assert_eq!(client.try_retry_callback(&memo), Err(Ok(Error::InvalidStatus)));
+    }
+
+    #[test]
+    fn test_has_paid() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let payer = Address::generate(&env);
+        let stranger = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_loyal");
+        client.register_session(&memo, &merchant, &100);
+        assert!(!client.has_paid(&merchant, &payer));
+        client.validate_payment_full(&memo, &100, &payer);
+        
+        assert!(client.has_paid(&merchant, &payer));
+        assert!(!client.has_paid(&merchant, &stranger));
+        assert!(!client.has_paid(&Address::generate(&env), &payer));
+    }
+
+    #[test]
+    fn test_tolerance_bps() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let options = SessionOptions { tolerance_bps: 50, ..Default::default() };
+        
+        // 0.5% of 10_000 is 50
+        let within = String::from_str(&env, "pay_tol_ok");
+        client.register_session_with(&within, &merchant, &10_000, &options);
+        assert!(client.validate_payment(&within, &9_950));
+        assert_eq!(client.get_session(&within).unwrap().actual_amount, Some(9_950));
+        
+        let beyond = String::from_str(&env, "pay_tol_short");
+        client.register_session_with(&beyond, &merchant, &10_000, &options);
+        assert_eq!(client.try_validate_payment(&beyond, &9_949), Err(Ok(Error::InsufficientAmount)));
+    }
+
+    #[test]
+    fn test_get_active_session_count() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        assert_eq!(client.get_active_session_count(&merchant), 0);
+        
+        for memo in ["pay_open_1", "pay_open_2", "pay_open_3"] {
+            client.register_session(&String::from_str(&env, memo), &merchant, &100);
+        }
+        client.deactivate_session(&String::from_str(&env, "pay_open_2"));
+        assert_eq!(client.get_active_session_count(&merchant), 2);
+    }
+
+    #[test]
+    fn test_refund_window() {
+        let env = Env::default();
+        let config = InitConfig { refund_window_seconds: 30 * 86_400, ..Default::default() };
+        let (client, _, _) = setup_with(&env, config);
+        let merchant = Address::generate(&env);
+        
+        let within = String::from_str(&env, "pay_refund_ok");
+        let late = String::from_str(&env, "pay_refund_late");
+        client.register_session(&within, &merchant, &100);
+        client.register_session(&late, &merchant, &100);
+        client.validate_payment(&within, &100);
+        client.validate_payment(&late, &100);
+        assert_eq!(client.try_mark_refunded(&within), Ok(Ok(())));
+        assert_eq!(client.get_session(&within).unwrap().status, SessionStatus::Refunded);
+        
+        env.ledger().with_mut(|li| li.timestamp += 30 * 86_400 + 1);
+        assert_eq!(client.try_mark_refunded(&late), Err(Ok(Error::RefundWindowClosed)));
+    }
+
+    #[test]
+    fn test_session_priority() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memos = ["pay_prio_0", "pay_prio_1", "pay_prio_2", "pay_prio_3"];
+        for (priority, memo) in memos.iter().enumerate() {
+            let memo = String::from_str(&env, memo);
+            let options = SessionOptions { priority: priority as u32, ..Default::default() };
+            client.register_session_with(&memo, &merchant, &100, &options);
+            assert_eq!(client.get_session(&memo).unwrap().priority, priority as u32);
+        }
+        
+        client.validate_payment(&String::from_str(&env, "pay_prio_2"), &100);
+        let (_, _, data) = env.events().all().last().unwrap();
+        let (_, event): (u64, CompletionEvent) = data.into_val(&env);
+        assert_eq!(event.priority, 2);
+        
+        let options = SessionOptions { priority: MAX_PRIORITY + 1, ..Default::default() };
+        assert_eq!(
+            client.try_register_session_with(&String::from_str(&env, "pay_prio_x"), &merchant, &100, &options),
+            Err(Ok(Error::InvalidPriority))
+        );
+    }
+
+    #[test]
+    fn test_bump_sessions() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let ttl = |memo: String| {
+            env.as_contract(&client.address, || {
+                env.storage().persistent().get_ttl(&DataKey::Session(memo))
+            })
+        };
+        
+        let mut memos = Vec::new(&env);
+        let mut before = Vec::new(&env);
+        for memo in ["pay_bump_1", "pay_bump_2"] {
+            let memo = String::from_str(&env, memo);
+            client.register_session(&memo, &merchant, &100);
+            before.push_back(ttl(memo.clone()));
+            memos.push_back(memo);
+        }
+        
+        memos.push_back(String::from_str(&env, "pay_bump_missing"));
+        assert_eq!(client.bump_sessions(&memos, &100_000), 2);
+        for i in 0..2 {
+            assert!(ttl(memos.get(i).unwrap()) > before.get(i).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_update_amount_cas() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let memo = String::from_str(&env, "pay_cas");
+        client.register_session(&memo, &merchant, &100);
+        
+        client.update_amount_cas(&memo, &100, &150);
+        assert_eq!(client.get_session(&memo).unwrap().amount, 150);
+        assert_eq!(client.get_total_outstanding(), 150);
+        
+        // A second writer still holding the old amount loses
+        assert_eq!(client.try_update_amount_cas(&memo, &100, &120), Err(Ok(Error::CasMismatch)));
+        assert_eq!(client.get_session(&memo).unwrap().amount, 150);
+    }
+
+    #[test]
+    fn test_get_status_counts() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        for memo in ["pay_sc_open", "pay_sc_paid", "pay_sc_cancel"] {
+            client.register_session(&String::from_str(&env, memo), &merchant, &100);
+        }
+        let lapsing = String::from_str(&env, "pay_sc_lapse");
+        let options = SessionOptions { ttl_seconds: 60, ..Default::default() };
+        client.register_session_with(&lapsing, &merchant, &100, &options);
+        
+        client.validate_payment(&String::from_str(&env, "pay_sc_paid"), &100);
+        client.deactivate_session(&String::from_str(&env, "pay_sc_cancel"));
+        env.ledger().with_mut(|li| li.timestamp += 61);
+        client.deactivate_session(&lapsing);
+        
+        let counts = client.get_status_counts();
+        assert_eq!(counts, StatusCounts { active: 1, completed: 1, cancelled: 1, expired: 1 });
+    }
+
+    #[test]
+    fn test_fee_recipient() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        client.set_global_fee_bps(&100);
+        let merchant = Address::generate(&env);
+        let affiliate = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_affiliate");
+        let options = SessionOptions { fee_recipient: Some(affiliate.clone()), ..Default::default() };
+        client.register_session_with(&memo, &merchant, &10_000, &options);
+        client.validate_payment(&memo, &10_000);
+        
+        let (_, _, data) = env.events().all().last().unwrap();
+        let (_, event): (u64, CompletionEvent) = data.into_val(&env);
+        assert_eq!(event.fee, 100);
+        assert_eq!(event.fee_recipient, Some(affiliate));
+    }
+
+    #[test]
+    fn test_asset_max() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let capped = Address::generate(&env);
+        let other = Address::generate(&env);
+        client.set_asset_max(&capped, &1_000);
+        
+        let in_capped = SessionOptions { asset: Some(capped), ..Default::default() };
+        let within = String::from_str(&env, "pay_cap_ok");
+        client.register_session_with(&within, &merchant, &1_000, &in_capped);
+        let over = String::from_str(&env, "pay_cap_over");
+        assert_eq!(
+            client.try_register_session_with(&over, &merchant, &1_001, &in_capped),
+            Err(Ok(Error::InvalidAmount))
+        );
+        
+        // The cap holds for amount changes after registration
+        assert_eq!(
+            client.try_update_amount_cas(&within, &1_000, &1_001),
+            Err(Ok(Error::InvalidAmount))
+        );
+        client.update_amount_cas(&within, &1_000, &900);
+        
+        let in_other = SessionOptions { asset: Some(other), ..Default::default() };
+        let uncapped = String::from_str(&env, "pay_cap_other");
+        client.register_session_with(&uncapped, &merchant, &5_000, &in_other);
+    }
+
+    #[test]
+    fn test_is_awaiting_payment() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let paid = String::from_str(&env, "pay_await_paid");
+        let lapsing = String::from_str(&env, "pay_await_lapse");
+        client.register_session(&paid, &merchant, &100);
+        let options = SessionOptions { ttl_seconds: 60, ..Default::default() };
+        client.register_session_with(&lapsing, &merchant, &100, &options);
+        assert!(client.is_awaiting_payment(&paid));
+        assert!(client.is_awaiting_payment(&lapsing));
+        assert!(!client.is_awaiting_payment(&String::from_str(&env, "pay_await_none")));
+        
+        client.validate_payment(&paid, &100);
+        assert!(!client.is_awaiting_payment(&paid));
+        
+        env.ledger().with_mut(|li| li.timestamp += 60);
+        assert!(!client.is_awaiting_payment(&lapsing));
+    }
+
+    #[test]
+    fn test_reactivate_session_event() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let memo = String::from_str(&env, "pay_react");
+        
+        client.register_session(&memo, &merchant, &100);
+        let (_, topics, _) = env.events().all().last().unwrap();
+        assert_eq!(topics, (symbol_short!("reg_sess"),).into_val(&env));
+        
+        client.deactivate_session(&memo);
+        env.ledger().with_mut(|li| li.timestamp = 5_000);
+        client.reactivate_session(&memo);
+        let (_, topics, data) = env.events().all().last().unwrap();
+        assert_eq!(topics, (symbol_short!("react"),).into_val(&env));
+        let (_, (reactivated, at)): (u64, (String, u64)) = data.into_val(&env);
+        assert_eq!((reactivated, at), (memo.clone(), 5_000));
+        
+        assert!(client.is_awaiting_payment(&memo));
+        assert_eq!(client.get_status_counts().cancelled, 0);
+    }
+
+    #[test]
+    fn test_reactivate_rechecks_gates() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let memo = String::from_str(&env, "pay_offboard");
+        client.approve_merchant(&merchant);
+        client.register_session(&memo, &merchant, &100);
+        client.deactivate_session(&memo);
+        
+        // An offboarded merchant's session stays closed
+        client.revoke_merchant(&merchant);
+        assert_eq!(client.try_reactivate_session(&memo), Err(Ok(Error::MerchantNotApproved)));
+        client.approve_merchant(&merchant);
+        
+        // and nothing reopens while registration is paused
+        client.pause_registration();
+        assert_eq!(client.try_reactivate_session(&memo), Err(Ok(Error::ContractPaused)));
+        assert_eq!(client.get_session(&memo).unwrap().status, SessionStatus::Cancelled);
+        
+        client.unpause_registration();
+        client.reactivate_session(&memo);
+        assert!(client.is_awaiting_payment(&memo));
+    }
+
+    #[test]
+    fn test_get_merchant_assets() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let usdc = Address::generate(&env);
+        let eurc = Address::generate(&env);
+        
+        let memos = ["pay_ma_1", "pay_ma_2", "pay_ma_3"];
+        for (memo, asset) in memos.iter().zip([&usdc, &eurc, &usdc]) {
+            let memo = String::from_str(&env, memo);
+            let options = SessionOptions { asset: Some(asset.clone()), ..Default::default() };
+            client.register_session_with(&memo, &merchant, &100, &options);
+            client.validate_payment(&memo, &100);
+        }
+        // A registered but unpaid session doesn't count
+        let pending = SessionOptions { asset: Some(Address::generate(&env)), ..Default::default() };
+        client.register_session_with(&String::from_str(&env, "pay_ma_4"), &merchant, &100, &pending);
+        
+        assert_eq!(client.get_merchant_assets(&merchant), Vec::from_array(&env, [usdc, eurc]));
+    }
+
+    #[test]
+    fn test_deactivation_lock() {
+        let env = Env::default();
+        let config = InitConfig { deactivation_lock_seconds: 300, ..Default::default() };
+        let (client, _, _) = setup_with(&env, config);
+        let merchant = Address::generate(&env);
+        let memo = String::from_str(&env, "pay_locked");
+        let forced = String::from_str(&env, "pay_forced");
+        client.register_session(&memo, &merchant, &100);
+        client.register_session(&forced, &merchant, &100);
+        
+        assert_eq!(client.try_deactivate_session(&memo), Err(Ok(Error::DeactivationLocked)));
+        client.force_deactivate(&forced);
+        assert!(!client.get_session(&forced).unwrap().is_active);
+        
+        env.ledger().with_mut(|li| li.timestamp += 300);
+        client.deactivate_session(&memo);
+        assert!(!client.get_session(&memo).unwrap().is_active);
+    }
+
+    #[test]
+    fn test_platform_fees() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        client.set_global_fee_bps(&100);
+        let merchant = Address::generate(&env);
+        let usdc = Address::generate(&env);
+        let options = SessionOptions { asset: Some(usdc.clone()), ..Default::default() };
+        
+        for (memo, amount) in [("pay_pf_1", 10_000), ("pay_pf_2", 5_000)] {
+            let memo = String::from_str(&env, memo);
+            client.register_session_with(&memo, &merchant, &amount, &options);
+            client.validate_payment(&memo, &amount);
+        }
+        assert_eq!(client.get_platform_fees(&usdc), 150);
+        
+        client.reset_platform_fees(&usdc);
+        assert_eq!(client.get_platform_fees(&usdc), 0);
+    }
+
+    #[test]
+    fn test_get_amount_range() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let minimum = String::from_str(&env, "pay_rng_min");
+        client.register_session(&minimum, &merchant, &1_000);
+        assert_eq!(client.get_amount_range(&minimum), (1_000, i128::MAX));
+        
+        let exact = String::from_str(&env, "pay_rng_exact");
+        let options = SessionOptions { amount_mode: AmountMode::Exact, ..Default::default() };
+        client.register_session_with(&exact, &merchant, &1_000, &options);
+        assert_eq!(client.get_amount_range(&exact), (1_000, 1_000));
+        
+        // A bounded band: 1% short up to 50 over
+        let band = String::from_str(&env, "pay_rng_band");
+        let options = SessionOptions { tolerance_bps: 100, max_overpayment: 50, ..Default::default() };
+        client.register_session_with(&band, &merchant, &1_000, &options);
+        assert_eq!(client.get_amount_range(&band), (990, 1_050));
+    }
+
+    #[test]
+    fn test_get_stuck_partials() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let options = SessionOptions { ttl_seconds: 60, ..Default::default() };
+        
+        let stuck = String::from_str(&env, "pay_stuck");
+        let paid = String::from_str(&env, "pay_stuck_paid");
+        let untouched = String::from_str(&env, "pay_stuck_none");
+        for memo in [&stuck, &paid, &untouched] {
+            client.register_session_with(memo, &merchant, &100, &options);
+        }
+        client.record_partial_payment(&stuck, &40);
+        client.record_partial_payment(&paid, &40);
+        client.record_partial_payment(&paid, &60);
+        env.ledger().with_mut(|li| li.timestamp += 60);
+        
+        let memos = Vec::from_array(&env, [stuck.clone(), paid, untouched]);
+        assert_eq!(client.get_stuck_partials(&memos), Vec::from_array(&env, [stuck]));
+    }
+
+    #[test]
+    fn test_idempotency_key() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let memo = String::from_str(&env, "pay_idem");
+        let key = String::from_str(&env, "req-7f3a");
+        let options = SessionOptions { idempotency_key: Some(key), ..Default::default() };
+        
+        client.register_session_with(&memo, &merchant, &100, &options);
+        assert_eq!(client.get_session_count(), 1);
+        
+        // The retry succeeds without creating anything, even under a fresh memo
+        client.register_session_with(&memo, &merchant, &100, &options);
+        let fresh = String::from_str(&env, "pay_idem_retry");
+        client.register_session_with(&fresh, &merchant, &100, &options);
+        assert_eq!(client.get_session_count(), 1);
+        
+        // A retry asking for something else is a caller bug, not a retry
+        assert_eq!(
+            client.try_register_session_with(&memo, &Address::generate(&env), &100, &options),
+            Err(Ok(Error::SessionAlreadyExists))
+        );
+        assert_eq!(
+            client.try_register_session_with(&fresh, &merchant, &200, &options),
+            Err(Ok(Error::SessionAlreadyExists))
+        );
+        assert_eq!(client.get_session_count(), 1);
+        
+        let other_key = Some(String::from_str(&env, "req-9c1d"));
+        let other = SessionOptions { idempotency_key: other_key, ..Default::default() };
+        assert_eq!(
+            client.try_register_session_with(&memo, &merchant, &100, &other),
+            Err(Ok(Error::SessionAlreadyExists))
+        );
+    }
+
+    #[test]
+    fn test_get_nonce() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        assert_eq!(client.get_nonce(), 0);
         
-        let backend = Address::generate(&env);
+        let memo = String::from_str(&env, "pay_nonce_1");
+        client.register_session(&memo, &merchant, &100);
+        assert_eq!(client.get_nonce(), 1);
+        client.register_session(&String::from_str(&env, "pay_nonce_2"), &merchant, &100);
+        assert_eq!(client.get_nonce(), 2);
+        
+        assert!(client.try_register_session(&memo, &merchant, &100).is_err());
+        assert_eq!(client.get_nonce(), 2);
+    }
+
+    #[test]
+    fn test_approval_threshold() {
+        let env = Env::default();
+        let approved = Address::generate(&env);
+        let config = InitConfig {
+            approved_merchants: Some(Vec::from_array(&env, [approved.clone()])),
+            approval_threshold: 1_000,
+            ..Default::default()
+        };
+        let (client, _, _) = setup_with(&env, config);
+        let unapproved = Address::generate(&env);
+        
+        client.register_session(&String::from_str(&env, "pay_kyc_small"), &unapproved, &999);
+        assert_eq!(
+            client.try_register_session(&String::from_str(&env, "pay_kyc_large"), &unapproved, &1_000),
+            Err(Ok(Error::MerchantNotApproved))
+        );
+        client.register_session(&String::from_str(&env, "pay_kyc_ok"), &approved, &1_000);
+        
+        // Reassigning can't hand a large session to an unapproved merchant
+        assert_eq!(
+            client.try_reassign_merchant(&String::from_str(&env, "pay_kyc_ok"), &unapproved),
+            Err(Ok(Error::MerchantNotApproved))
+        );
+        client.reassign_merchant(&String::from_str(&env, "pay_kyc_small"), &approved);
+        
+        // Nor can an unapproved merchant's session be raised past the threshold
+        let small = String::from_str(&env, "pay_kyc_raise");
+        client.register_session(&small, &unapproved, &500);
+        assert_eq!(
+            client.try_update_amount_cas(&small, &500, &1_000),
+            Err(Ok(Error::MerchantNotApproved))
+        );
+        client.update_amount_cas(&small, &500, &999);
+    }
+
+    #[test]
+    fn test_completion_bps() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
         let merchant = Address::generate(&env);
-        let customer = Address::generate(&env);
+        let memo = String::from_str(&env, "pay_progress");
+        client.register_session(&memo, &merchant, &250);
         
-        // Initialize
-        client.initialize(&backend);
+        assert_eq!(client.completion_bps(&memo), 0);
+        client.record_partial_payment(&memo, &100);
+        assert_eq!(client.completion_bps(&memo), 4_000);
+        client.record_partial_payment(&memo, &150);
+        assert_eq!(client.completion_bps(&memo), 10_000);
         
-        // Register session
-        let memo = String::from_str(&env, "pay_test123");
+        let missing = String::from_str(&env, "pay_progress_none");
+        assert_eq!(client.try_completion_bps(&missing), Err(Ok(Error::SessionNotFound)));
+    }
+
+    #[test]
+    fn test_cancel_if_unpaid() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let unpaid = String::from_str(&env, "pay_unpaid");
+        let partial = String::from_str(&env, "pay_part_paid");
+        client.register_session(&unpaid, &merchant, &100);
+        client.register_session(&partial, &merchant, &100);
+        client.record_partial_payment(&partial, &30);
+        
+        client.cancel_if_unpaid(&unpaid);
+        assert_eq!(client.get_session(&unpaid).unwrap().status, SessionStatus::Cancelled);
+        assert_eq!(client.try_cancel_if_unpaid(&partial), Err(Ok(Error::HasPartialPayment)));
+        assert!(client.get_session(&partial).unwrap().is_active);
+    }
+
+    #[test]
+    fn test_get_statuses() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let open = String::from_str(&env, "pay_st_open");
+        let paid = String::from_str(&env, "pay_st_paid");
+        client.register_session(&open, &merchant, &100);
+        client.register_session(&paid, &merchant, &100);
+        client.validate_payment(&paid, &100);
+        
+        let memos = Vec::from_array(&env, [open, String::from_str(&env, "pay_st_none"), paid]);
+        let expected = Vec::from_array(
+            &env,
+            [Some(SessionStatus::Active), None, Some(SessionStatus::Completed)],
+        );
+        assert_eq!(client.get_statuses(&memos), expected);
+    }
+
+    #[test]
+    fn test_partial_cooldown() {
+        let env = Env::default();
+        let config = InitConfig { partial_cooldown_seconds: 30, ..Default::default() };
+        let (client, _, _) = setup_with(&env, config);
+        let merchant = Address::generate(&env);
+        let memo = String::from_str(&env, "pay_throttle");
         client.register_session(&memo, &merchant, &100);
         
-        // Verify session
-        let session = client.get_session(&memo);
-        assert!(session.is_some());
-        assert!(session.unwrap().is_active);
+        client.record_partial_payment(&memo, &10);
+        assert_eq!(client.try_record_partial_payment(&memo, &10), Err(Ok(Error::RateLimited)));
+        
+        env.ledger().with_mut(|li| li.timestamp += 30);
+        assert_eq!(client.record_partial_payment(&memo, &10), 80);
+    }
+
+    #[test]
+    fn test_get_age() {
+        let env = Env::default();
+        env.ledger().with_mut(|li| li.timestamp = 1_700_000_000);
+        let (client, _, _) = setup(&env);
+        assert_eq!(client.get_age(), 0);
+        
+        env.ledger().with_mut(|li| li.timestamp += 86_400);
+        assert_eq!(client.get_age(), 86_400);
+    }
+
+    #[test]
+    fn test_validate_before_ledger() {
+        let env = Env::default();
+        let (client, backend, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let on_time = String::from_str(&env, "pay_deadline_ok");
+        let late = String::from_str(&env, "pay_deadline_late");
+        client.register_session(&on_time, &merchant, &100);
+        client.register_session(&late, &merchant, &100);
+        
+        let deadline = env.ledger().sequence() + 3;
+        assert!(client.validate_before_ledger(&on_time, &100, &deadline));
+        assert_eq!(env.auths()[0].0, backend);
+        
+        env.ledger().with_mut(|li| li.sequence_number += 4);
+        assert_eq!(
+            client.try_validate_before_ledger(&late, &100, &deadline),
+            Err(Ok(Error::DeadlinePassed))
+        );
+        assert!(client.get_session(&late).unwrap().is_active);
+    }
+
+    #[test]
+    fn test_get_limits() {
+        let env = Env::default();
+        let config = InitConfig {
+            max_ttl_seconds: 3_600,
+            creation_fee: 5,
+            tick_size: 10,
+            max_sessions_per_merchant: 4,
+            registration_cooldown_seconds: 2,
+            min_age_seconds: 3,
+            extend_on_partial_seconds: 60,
+            partial_cooldown_seconds: 7,
+            refund_window_seconds: 86_400,
+            deactivation_lock_seconds: 120,
+            approval_threshold: 1_000,
+            min_donation: 50,
+            deadman_seconds: 86_400,
+            ..Default::default()
+        };
+        let (client, _, _) = setup_with(&env, config);
+        client.set_global_fee_bps(&250);
+        
+        let expected = ContractLimits {
+            fee_bps: 250,
+            max_ttl_seconds: 3_600,
+            creation_fee: 5,
+            tick_size: 10,
+            max_sessions_per_merchant: 4,
+            registration_cooldown_seconds: 2,
+            min_age_seconds: 3,
+            extend_on_partial_seconds: 60,
+            partial_cooldown_seconds: 7,
+            refund_window_seconds: 86_400,
+            deactivation_lock_seconds: 120,
+            approval_threshold: 1_000,
+            min_donation: 50,
+            deadman_seconds: 86_400,
+        };
+        assert_eq!(client.get_limits(), expected);
+        
+        let (defaults, _, _) = setup(&env);
+        assert_eq!(defaults.get_limits(), ContractLimits { tick_size: 1, ..Default::default() });
+    }
+
+    #[test]
+    fn test_seal() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let new_backend = Address::generate(&env);
+        
+        client.set_backend(&new_backend);
+        assert_eq!(client.get_controllers().backend, new_backend);
+        
+        client.seal();
+        assert!(client.is_sealed());
+        assert_eq!(client.try_set_backend(&new_backend), Err(Ok(Error::ContractSealed)));
+        assert_eq!(client.try_set_global_fee_bps(&100), Err(Ok(Error::ContractSealed)));
+        assert_eq!(client.try_pause_registration(), Err(Ok(Error::ContractSealed)));
+        assert_eq!(client.try_seal(), Err(Ok(Error::ContractSealed)));
+        
+        let memo = String::from_str(&env, "pay_sealed");
+        client.register_session(&memo, &merchant, &100);
+        assert!(client.validate_payment(&memo, &100));
+    }
+
+    #[test]
+    fn test_net_payout() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        client.set_global_fee_bps(&100);
+        let merchant = Address::generate(&env);
+        let exempt = Address::generate(&env);
+        client.set_fee_exempt(&exempt, &true);
+        
+        let custom = String::from_str(&env, "pay_net_custom");
+        let options = SessionOptions { fee_bps: Some(250), ..Default::default() };
+        client.register_session_with(&custom, &merchant, &10_000, &options);
+        assert_eq!(client.net_payout(&custom), 9_750);
+        
+        let global = String::from_str(&env, "pay_net_global");
+        client.register_session(&global, &merchant, &10_000);
+        assert_eq!(client.net_payout(&global), 9_900);
+        
+        let free = String::from_str(&env, "pay_net_exempt");
+        client.register_session(&free, &exempt, &10_000);
+        assert_eq!(client.net_payout(&free), 10_000);
+        
+        let missing = String::from_str(&env, "pay_net_none");
+        assert_eq!(client.try_net_payout(&missing), Err(Ok(Error::SessionNotFound)));
+    }
+
+    #[test]
+    fn test_fiat_code() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_fiat");
+        let usd = String::from_str(&env, "USD");
+        let options = SessionOptions { fiat_code: Some(usd.clone()), ..Default::default() };
+        client.register_session_with(&memo, &merchant, &100, &options);
+        client.validate_payment(&memo, &100);
+        let (_, _, data) = env.events().all().last().unwrap();
+        let (_, event): (u64, CompletionEvent) = data.into_val(&env);
+        assert_eq!(event.fiat_code, Some(usd));
+        
+        let too_long = Some(String::from_str(&env, "DOLLARSUS"));
+        let options = SessionOptions { fiat_code: too_long, ..Default::default() };
+        assert_eq!(
+            client.try_register_session_with(&String::from_str(&env, "pay_fiat_bad"), &merchant, &100, &options),
+            Err(Ok(Error::InvalidFiatCode))
+        );
+    }
+
+    #[test]
+    fn test_sessions_created_between() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let mut memos = Vec::new(&env);
+        for (memo, created_at) in [("pay_ts_100", 100), ("pay_ts_200", 200), ("pay_ts_300", 300)] {
+            env.ledger().with_mut(|li| li.timestamp = created_at);
+            let memo = String::from_str(&env, memo);
+            client.register_session(&memo, &merchant, &100);
+            memos.push_back(memo);
+        }
+        memos.push_back(String::from_str(&env, "pay_ts_none"));
+        
+        let selected = client.sessions_created_between(&memos, &150, &300);
+        let expected = Vec::from_array(
+            &env,
+            [String::from_str(&env, "pay_ts_200"), String::from_str(&env, "pay_ts_300")],
+        );
+        assert_eq!(selected, expected);
+    }
+
+    #[test]
+    fn test_record_partial_refund() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let memo = String::from_str(&env, "pay_part_refund");
+        client.register_session(&memo, &merchant, &1_000);
+        client.validate_payment(&memo, &1_000);
+        
+        client.record_partial_refund(&memo, &300);
+        let session = client.get_session(&memo).unwrap();
+        assert_eq!(session.refunded_amount, 300);
+        assert_eq!(session.status, SessionStatus::Completed);
+        assert_eq!(client.get_merchant_total(&merchant), 700);
+        
+        assert_eq!(client.try_record_partial_refund(&memo, &701), Err(Ok(Error::RefundExceedsPaid)));
+        client.record_partial_refund(&memo, &700);
+        assert_eq!(client.get_session(&memo).unwrap().status, SessionStatus::Refunded);
+        assert_eq!(client.get_merchant_total(&merchant), 0);
+    }
+
+    #[test]
+    fn test_role_of() {
+        let env = Env::default();
+        let (client, backend, admin) = setup(&env);
+        let merchant = Address::generate(&env);
+        client.approve_merchant(&merchant);
+        
+        assert_eq!(client.role_of(&backend), ROLE_BACKEND);
+        assert_eq!(client.role_of(&admin), ROLE_ADMIN);
+        assert_eq!(client.role_of(&merchant), ROLE_MERCHANT);
+        assert_eq!(client.role_of(&Address::generate(&env)), 0);
+    }
+
+    #[test]
+    fn test_deadman_switch() {
+        let env = Env::default();
+        let config = InitConfig { deadman_seconds: 3_600, ..Default::default() };
+        let (client, _, _) = setup_with(&env, config);
+        let merchant = Address::generate(&env);
+        let memo = String::from_str(&env, "pay_deadman");
+        client.register_session(&memo, &merchant, &100);
+        
+        // Backend calls inside the window keep it alive
+        env.ledger().with_mut(|li| li.timestamp += 3_600);
+        client.register_session(&String::from_str(&env, "pay_alive"), &merchant, &100);
+        
+        env.ledger().with_mut(|li| li.timestamp += 3_601);
+        assert_eq!(client.try_validate_payment(&memo, &100), Err(Ok(Error::ContractPaused)));
+        let late = String::from_str(&env, "pay_late");
+        assert_eq!(client.try_register_session(&late, &merchant, &100), Err(Ok(Error::ContractPaused)));
+        
+        client.reset_deadman();
+        assert!(client.validate_payment(&memo, &100));
+        client.register_session(&late, &merchant, &100);
+        
+        // Without an admin nobody could reset it, so the switch is refused
+        let unmanaged = ChainPeValidatorClient::new(&env, &env.register_contract(None, ChainPeValidator));
+        let config = InitConfig { deadman_seconds: 3_600, ..Default::default() };
+        assert!(unmanaged.try_initialize(&Address::generate(&env), &None, &config).is_err());
+    }
+
+    #[test]
+    fn test_register_session_with_link() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, " Pay_Link ");
+        let link = client.register_session_with_link(&memo, &merchant, &1_250_000);
+        assert_eq!(link, String::from_str(&env, "chainpe:pay_link:1250000"));
+        assert!(client.get_session(&String::from_str(&env, "pay_link")).unwrap().is_active);
+    }
+
+    #[test]
+    fn test_effective_fee_bps() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let exempt = Address::generate(&env);
+        client.set_fee_exempt(&exempt, &true);
+        client.set_global_fee_bps(&200);
+        assert_eq!(client.effective_fee_bps(&merchant, &500), 200);
+        
+        let tiers = Vec::from_array(&env, [FeeTier { min_amount: 10_000, bps: 100 }]);
+        client.set_fee_schedule(&tiers);
+        assert_eq!(client.effective_fee_bps(&merchant, &10_000), 100);
+        assert_eq!(client.effective_fee_bps(&merchant, &9_999), 200);
+        assert_eq!(client.effective_fee_bps(&exempt, &10_000), 0);
+    }
+
+    #[test]
+    fn test_disputes() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let upheld = String::from_str(&env, "pay_dispute_kept");
+        let reversed = String::from_str(&env, "pay_dispute_lost");
+        for memo in [&upheld, &reversed] {
+            client.register_session(memo, &merchant, &100);
+            client.validate_payment(memo, &100);
+            client.open_dispute(memo);
+        }
+        
+        assert_eq!(client.get_session(&upheld).unwrap().status, SessionStatus::Disputed);
+        assert_eq!(client.try_mark_refunded(&upheld), Err(Ok(Error::InvalidStatus)));
+        assert_eq!(client.try_record_partial_refund(&upheld, &10), Err(Ok(Error::InvalidStatus)));
+        assert_eq!(client.try_validate_payment(&upheld, &100), Err(Ok(Error::AlreadyCompleted)));
+        
+        client.resolve_dispute(&upheld, &true);
+        assert_eq!(client.get_session(&upheld).unwrap().status, SessionStatus::Completed);
+        client.resolve_dispute(&reversed, &false);
+        assert_eq!(client.get_session(&reversed).unwrap().status, SessionStatus::Refunded);
+        assert_eq!(client.try_resolve_dispute(&reversed, &true), Err(Ok(Error::InvalidStatus)));
+    }
+
+    #[test]
+    fn test_memo_prefix() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let unrestricted = Address::generate(&env);
+        client.set_memo_prefix(&merchant, &Some(String::from_str(&env, "acme_")));
+        
+        client.register_session(&String::from_str(&env, "ACME_order1"), &merchant, &100);
+        assert_eq!(
+            client.try_register_session(&String::from_str(&env, "other_order1"), &merchant, &100),
+            Err(Ok(Error::InvalidMemo))
+        );
+        client.register_session(&String::from_str(&env, "other_order1"), &unrestricted, &100);
+        
+        // Reassigning is held to the new merchant's prefix too
+        let foreign = String::from_str(&env, "other_order1");
+        assert_eq!(client.try_reassign_merchant(&foreign, &merchant), Err(Ok(Error::InvalidMemo)));
+        client.reassign_merchant(&String::from_str(&env, "acme_order1"), &unrestricted);
+        
+        client.set_memo_prefix(&merchant, &None);
+        client.register_session(&String::from_str(&env, "other_order2"), &merchant, &100);
+    }
+
+    #[test]
+    fn test_payment_count() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_count_partials");
+        let options = SessionOptions { max_partials: 3, ..Default::default() };
+        client.register_session_with(&memo, &merchant, &90, &options);
+        assert_eq!(client.get_payment_count(&memo), 0);
+        for _ in 0..3 {
+            client.record_partial_payment(&memo, &30);
+        }
+        assert_eq!(client.get_session(&memo).unwrap().status, SessionStatus::Completed);
+        assert_eq!(client.get_payment_count(&memo), 3);
+        
+        // A single validation is one payment
+        let single = String::from_str(&env, "pay_count_single");
+        client.register_session(&single, &merchant, &90);
+        client.validate_payment(&single, &90);
+        assert_eq!(client.get_payment_count(&single), 1);
+        
+        assert_eq!(
+            client.try_get_payment_count(&String::from_str(&env, "pay_count_missing")),
+            Err(Ok(Error::SessionNotFound))
+        );
+    }
+
+    #[test]
+    fn test_failing_callback_logged() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let receiver = env.register_contract(None, CallbackReceiver);
+        
+        let memo = String::from_str(&env, "pay_callback_fail");
+        let options = SessionOptions { callback: Some(receiver.clone()), ..Default::default() };
+        client.register_session_with(&memo, &merchant, &100, &options);
+        assert!(client.validate_payment(&memo, &100));
+        assert_eq!(client.get_session(&memo).unwrap().status, SessionStatus::Completed);
+        
+        let topics: soroban_sdk::Vec<soroban_sdk::Val> = (symbol_short!("cb_fail"),).into_val(&env);
+        let logged = env.events().all().iter().find(|(_, event_topics, _)| *event_topics == topics);
+        let (_, _, data) = logged.expect("cb_fail event");
+        let (_, failure): (u64, (String, Address, u32)) = data.into_val(&env);
+        assert_eq!(failure, (memo, receiver, 1));
+    }
+
+    #[test]
+    fn test_same_merchant() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let other = Address::generate(&env);
+        let first = String::from_str(&env, "pay_same_1");
+        let second = String::from_str(&env, "pay_same_2");
+        let foreign = String::from_str(&env, "pay_same_other");
+        let missing = String::from_str(&env, "pay_same_missing");
+        client.register_session(&first, &merchant, &100);
+        client.register_session(&second, &merchant, &100);
+        client.register_session(&foreign, &other, &100);
+        
+        let shared = Vec::from_array(&env, [first.clone(), missing.clone(), second]);
+        assert_eq!(client.same_merchant(&shared), Some(merchant));
+        assert_eq!(client.same_merchant(&Vec::from_array(&env, [first, foreign])), None);
+        assert_eq!(client.same_merchant(&Vec::from_array(&env, [missing])), None);
+        assert_eq!(client.same_merchant(&Vec::new(&env)), None);
+    }
+
+    #[test]
+    fn test_lapsed_escrow_refunded() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let options = SessionOptions { escrow: true, ttl_seconds: 100, ..Default::default() };
+        let touched = String::from_str(&env, "pay_lapsed_touch");
+        let swept = String::from_str(&env, "pay_lapsed_sweep");
+        let live = String::from_str(&env, "pay_lapsed_live");
+        for memo in [&touched, &swept] {
+            client.register_session_with(memo, &merchant, &100, &options);
+            client.validate_payment(memo, &100);
+        }
+        assert_eq!(
+            client.get_session(&touched).unwrap().hold_expires_at,
+            Some(HOLD_WINDOW_SECONDS)
+        );
+        env.ledger().with_mut(|ledger| ledger.timestamp += HOLD_WINDOW_SECONDS);
+        client.register_session_with(&live, &merchant, &100, &options);
+        client.validate_payment(&live, &100);
+        
+        // Releasing a lapsed hold refunds it instead
+        assert!(!client.release_escrow(&touched));
+        assert_eq!(client.get_session(&touched).unwrap().status, SessionStatus::Refunded);
+        let (_, topics, data) = env.events().all().last().unwrap();
+        assert_eq!(topics, (symbol_short!("refund"),).into_val(&env));
+        let (_, refund): (u64, (String, Address)) = data.into_val(&env);
+        assert_eq!(refund, (touched.clone(), merchant.clone()));
+        
+        let memos = Vec::from_array(&env, [touched, swept.clone(), live.clone()]);
+        assert_eq!(client.sweep_escrow(&memos), 1);
+        assert_eq!(client.get_session(&swept).unwrap().status, SessionStatus::Refunded);
+        assert_eq!(client.get_session(&live).unwrap().status, SessionStatus::Held);
+        assert!(client.release_escrow(&live));
+    }
+
+    #[test]
+    fn test_export_merchant() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let memos = [
+            String::from_str(&env, "pay_export_1"),
+            String::from_str(&env, "pay_export_2"),
+            String::from_str(&env, "pay_export_3"),
+        ];
+        for (memo, amount) in memos.iter().zip([100i128, 200, 300]) {
+            client.register_session(memo, &merchant, &amount);
+            env.ledger().with_mut(|ledger| ledger.timestamp += 10);
+        }
+        
+        let first = client.export_merchant(&merchant, &0, &2);
+        assert_eq!(first.len(), 2);
+        assert_eq!(
+            first.get(1).unwrap(),
+            SessionSummary {
+                memo: memos[1].clone(),
+                status: SessionStatus::Active,
+                amount: 200,
+                created_at: 10,
+            }
+        );
+        let rest = client.export_merchant(&merchant, &2, &2);
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest.get(0).unwrap().memo, memos[2]);
+        assert!(client.export_merchant(&merchant, &3, &2).is_empty());
+    }
+
+    #[test]
+    fn test_validate_merchant_signed() {
+        let env = Env::default();
+        let (client, backend, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_merchant_signed");
+        client.register_session(&memo, &merchant, &100);
+        
+        let args = (memo.clone(), 100i128).into_val(&env);
+        let invoke = MockAuthInvoke {
+            contract: &client.address,
+            fn_name: "validate_merchant_signed",
+            args,
+            sub_invokes: &[],
+        };
+        
+        // Backend alone is not enough
+        env.mock_auths(&[MockAuth { address: &backend, invoke: &invoke }]);
+        assert!(client.try_validate_merchant_signed(&memo, &100).is_err());
+        assert!(client.get_session(&memo).unwrap().is_active);
+        
+        // Backend plus merchant signatures complete the session
+        env.mock_auths(&[
+            MockAuth { address: &backend, invoke: &invoke },
+            MockAuth { address: &merchant, invoke: &invoke },
+        ]);
+        assert!(client.validate_merchant_signed(&memo, &100));
+        assert_eq!(client.get_session(&memo).unwrap().status, SessionStatus::Completed);
+    }
+
+    #[test]
+    fn test_require_merchant_auth() {
+        let env = Env::default();
+        let (client, backend, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        
+        let memo = String::from_str(&env, "pay_merchant_only");
+        let options = SessionOptions { require_merchant_auth: true, ..Default::default() };
+        client.register_session_with(&memo, &merchant, &100, &options);
+        
+        // Without the merchant's signature no completion path goes through
+        env.mock_auths(&[]);
+        assert!(client.try_validate_payment(&memo, &100).is_err());
+        let args = (memo.clone(), 100i128).into_val(&env);
+        let partial = MockAuthInvoke {
+            contract: &client.address,
+            fn_name: "record_partial_payment",
+            args,
+            sub_invokes: &[],
+        };
+        env.mock_auths(&[MockAuth { address: &backend, invoke: &partial }]);
+        assert!(client.try_record_partial_payment(&memo, &100).is_err());
+        assert!(client.get_session(&memo).unwrap().is_active);
+        
+        let args = (memo.clone(), 100i128).into_val(&env);
+        let invoke = MockAuthInvoke {
+            contract: &client.address,
+            fn_name: "validate_merchant_signed",
+            args,
+            sub_invokes: &[],
+        };
+        env.mock_auths(&[
+            MockAuth { address: &backend, invoke: &invoke },
+            MockAuth { address: &merchant, invoke: &invoke },
+        ]);
+        assert!(client.validate_merchant_signed(&memo, &100));
+        assert_eq!(client.get_session(&memo).unwrap().status, SessionStatus::Completed);
+    }
+
+    #[test]
+    fn test_get_timestamps() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        env.ledger().with_mut(|ledger| ledger.timestamp = 1_000);
+        
+        let memo = String::from_str(&env, "pay_timestamps");
+        let options = SessionOptions { ttl_seconds: 600, ..Default::default() };
+        client.register_session_with(&memo, &merchant, &100, &options);
+        let registered = client.get_timestamps(&memo);
+        assert_eq!(registered.created_at, 1_000);
+        assert_eq!(registered.completed_at, None);
+        assert_eq!(registered.expires_at, Some(1_600));
+        
+        env.ledger().with_mut(|ledger| ledger.timestamp = 1_200);
+        client.validate_payment(&memo, &100);
+        let completed = client.get_timestamps(&memo);
+        assert_eq!(completed.completed_at, Some(1_200));
+        assert!(completed.created_at < completed.completed_at.unwrap());
+        assert_eq!(completed.last_activity, 1_200);
+        
+        assert_eq!(
+            client.try_get_timestamps(&String::from_str(&env, "pay_timestamps_missing")),
+            Err(Ok(Error::SessionNotFound))
+        );
+    }
+
+    #[test]
+    fn test_release_after_checkout_expiry() {
+        let env = Env::default();
+        let config = InitConfig { max_ttl_seconds: 100, ..Default::default() };
+        let (client, _, _) = setup_with(&env, config);
+        let merchant = Address::generate(&env);
+        
+        // ttl 0 takes the max TTL, so the session has a checkout expiry
+        let memo = String::from_str(&env, "pay_hold_outlives_checkout");
+        let options = SessionOptions { escrow: true, ..Default::default() };
+        client.register_session_with(&memo, &merchant, &100, &options);
+        client.validate_payment(&memo, &100);
+        assert_eq!(client.get_session(&memo).unwrap().expires_at, Some(100));
+        
+        // Past the checkout expiry but inside the hold window, release still completes it
+        env.ledger().with_mut(|ledger| ledger.timestamp = 1_000);
+        assert_eq!(client.sweep_escrow(&Vec::from_array(&env, [memo.clone()])), 0);
+        assert!(client.release_escrow(&memo));
+        assert_eq!(client.get_session(&memo).unwrap().status, SessionStatus::Completed);
+    }
+
+    #[test]
+    fn test_paged_index() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let index = Index::Merchant(Address::generate(&env));
+        env.budget().reset_unlimited();
+        
+        env.as_contract(&client.address, || {
+            let mut memos = Vec::new(&env);
+            for n in 0..(INDEX_PAGE_SIZE * 2 + 5) {
+                let mut memo = [b'm', b'_', b'0', b'0', b'0'];
+                memo[2] = b'0' + (n / 100) as u8;
+                memo[3] = b'0' + (n / 10 % 10) as u8;
+                memo[4] = b'0' + (n % 10) as u8;
+                let memo = String::from_bytes(&env, &memo);
+                paged_push(&env, &index, &memo);
+                memos.push_back(memo);
+            }
+            assert_eq!(paged_len(&env, &index), INDEX_PAGE_SIZE * 2 + 5);
+            
+            // Each page entry stays bounded
+            let key = DataKey::Index(IndexEntry::Page(index.clone(), 0));
+            let first: Vec<String> = env.storage().persistent().get(&key).unwrap();
+            assert_eq!(first.len(), INDEX_PAGE_SIZE);
+            
+            // A range spanning pages reads in position order
+            let start = INDEX_PAGE_SIZE - 2;
+            assert_eq!(paged_range(&env, &index, start, 4), memos.slice(start..start + 4));
+            assert_eq!(paged_range::<String>(&env, &index, INDEX_PAGE_SIZE * 2 + 3, 10).len(), 2);
+            
+            // Removing from the middle moves the last memo into its place
+            paged_remove(&env, &index, &memos.get(1).unwrap());
+            let remaining = paged_len(&env, &index);
+            assert_eq!(remaining, INDEX_PAGE_SIZE * 2 + 4);
+            assert_eq!(paged_range(&env, &index, 1, 1).get(0), memos.last());
+            for _ in 0..remaining {
+                let memo: String = paged_range(&env, &index, 0, 1).get(0).unwrap();
+                paged_remove(&env, &index, &memo);
+            }
+            assert_eq!(paged_len(&env, &index), 0);
+            assert!(!env.storage().persistent().has(&key));
+        });
+    }
+
+    #[test]
+    fn test_memo_spellings_agree() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let spelled = String::from_str(&env, " Pay_Mixed ");
+        let canonical = String::from_str(&env, "pay_mixed");
+        
+        client.register_session(&canonical, &merchant, &100);
+        assert_eq!(client.get_session(&spelled).unwrap().memo, canonical);
+        let statuses = client.get_statuses(&Vec::from_array(&env, [spelled.clone()]));
+        assert_eq!(statuses.get(0).unwrap(), Some(SessionStatus::Active));
+        client.update_amount_cas(&spelled, &100, &200);
+        client.deactivate_session(&spelled);
+        assert_eq!(client.get_session(&canonical).unwrap().status, SessionStatus::Cancelled);
+        client.reactivate_session(&canonical);
+        
+        // Authorizations and closed-memo reservations are keyed canonically too
+        client.authorize(&spelled, &200);
+        assert!(client.capture(&spelled));
+        let closed = String::from_str(&env, "pay_mixed_closed");
+        client.register_session(&String::from_str(&env, "PAY_MIXED_CLOSED"), &merchant, &100);
+        client.close_session(&String::from_str(&env, " pay_mixed_closed"));
+        assert_eq!(client.get_session(&closed), None);
+        assert_eq!(
+            client.try_register_session(&closed, &merchant, &100),
+            Err(Ok(Error::SessionAlreadyExists))
+        );
+    }
+
+    #[test]
+    fn test_refunds_reduce_merchant_total() {
+        let env = Env::default();
+        let (client, _, _) = setup(&env);
+        let merchant = Address::generate(&env);
+        let escrow = SessionOptions { escrow: true, ..Default::default() };
+        let marked = String::from_str(&env, "pay_total_marked");
+        let disputed = String::from_str(&env, "pay_total_disputed");
+        let held = String::from_str(&env, "pay_total_held");
+        let lapsed = String::from_str(&env, "pay_total_lapsed");
+        client.register_session(&marked, &merchant, &100);
+        client.register_session(&disputed, &merchant, &200);
+        client.register_session_with(&held, &merchant, &300, &escrow);
+        client.register_session_with(&lapsed, &merchant, &400, &escrow);
+        for (memo, amount) in [(&marked, 100i128), (&disputed, 200), (&held, 300), (&lapsed, 400)] {
+            client.validate_payment(memo, &amount);
+        }
+        assert_eq!(client.get_merchant_total(&merchant), 1_000);
+        
+        // A partial refund already came off; marking refunded takes only the rest
+        client.record_partial_refund(&marked, &40);
+        client.mark_refunded(&marked);
+        assert_eq!(client.get_merchant_total(&merchant), 900);
+        
+        client.open_dispute(&disputed);
+        client.resolve_dispute(&disputed, &false);
+        assert_eq!(client.get_merchant_total(&merchant), 700);
+        
+        client.refund_escrow(&held);
+        assert_eq!(client.get_merchant_total(&merchant), 400);
+        
+        env.ledger().with_mut(|ledger| ledger.timestamp += HOLD_WINDOW_SECONDS);
+        assert!(!client.release_escrow(&lapsed));
+        assert_eq!(client.get_merchant_total(&merchant), 0);
+        assert_eq!(client.get_session(&lapsed).unwrap().refunded_amount, 400);
     }
 }